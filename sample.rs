@@ -5,8 +5,8 @@ use xtb_rs::*;
 fn main() {
     let mut xtb = XTB::connect(1337, "password").unwrap();
     std::thread::sleep(Duration::from_secs(1));
-    xtb.start_balance().unwrap();
-    xtb.start_trades().unwrap();
+    let _balance = xtb.start_balance().unwrap();
+    let _trades = xtb.start_trades().unwrap();
     let order = xtb.buy("EURUSD", 0.01).unwrap();
     let timer = std::time::Instant::now();
     let mut actual_order = None;
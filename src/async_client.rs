@@ -0,0 +1,1055 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use futures_core::Stream;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, WriteHalf,
+};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+use crate::calls::{self, Call};
+use crate::commands::*;
+use crate::history::Windows;
+use crate::rate_limit::{RateLimit, RateLimiter};
+use crate::step::StepRules;
+use crate::subscription::{ActiveStreams, StreamRelease};
+use crate::transport::{dns_name, RUSTLS_CLIENT_CONFIG};
+use crate::types::*;
+use crate::{
+    CalendarRecord, Environment, ErrorKind, PendingOptions, PriceRounder, Result, ServerClock,
+    StepRule, StreamKind, Subscription, TradingCalendar, TradingHoursRecord, Transaction,
+    TransportKind, XtbConfig, KEEP_ALIVE_TIMEOUT, TRANSACTION_STATUS_POLL_INTERVAL,
+};
+
+type Tls = TlsStream<TcpStream>;
+
+fn disconnected() -> ErrorKind {
+    ErrorKind::Disconnected(std::io::ErrorKind::NotConnected.into())
+}
+
+async fn connect_tls(host: &str, port: u16, timeout: Option<Duration>) -> Result<Tls> {
+    let dns_name = dns_name(host)?;
+    let connect = TcpStream::connect((host, port));
+    let socket = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, connect)
+            .await
+            .map_err(|_| ErrorKind::Timeout)?,
+        None => connect.await,
+    }
+    .map_err(ErrorKind::Disconnected)?;
+
+    TlsConnector::from(RUSTLS_CLIENT_CONFIG.clone())
+        .connect(dns_name, socket)
+        .await
+        .map_err(ErrorKind::Disconnected)
+}
+
+/// Reads the next non-empty line into `buffer`.
+///
+/// Can be cancelled and called again, as long as `buffer` is kept until the
+/// line has been used.
+async fn next_line<R: AsyncBufRead + Unpin>(reader: &mut R, buffer: &mut Vec<u8>) -> Result<()> {
+    loop {
+        let read = reader
+            .read_until(b'\n', buffer)
+            .await
+            .map_err(ErrorKind::Disconnected)?;
+        if read == 0 {
+            return Err(ErrorKind::Disconnected(
+                std::io::ErrorKind::UnexpectedEof.into(),
+            ));
+        }
+        // Messages are separated by an empty line
+        if buffer.iter().all(u8::is_ascii_whitespace) {
+            buffer.clear();
+            continue;
+        }
+        return Ok(());
+    }
+}
+
+async fn acquire(limiter: &mut RateLimiter) {
+    while let Err(wait) = limiter.try_acquire() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+fn take_message<D: DeserializeOwned>(buffer: &mut Vec<u8>) -> Result<D> {
+    let message = serde_json::from_slice(buffer).map_err(ErrorKind::JsonError);
+    buffer.clear();
+    message
+}
+
+struct RequestConnection<S = Tls> {
+    stream: BufReader<S>,
+    buffer: Vec<u8>,
+    timeout: Option<Duration>,
+    limiter: RateLimiter,
+    /// Set while a reply is outstanding. If it's still set when the next
+    /// request starts, the previous one was cancelled or timed out and its
+    /// reply would be taken for the answer to this one.
+    poisoned: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> RequestConnection<S> {
+    fn new(stream: S, timeout: Option<Duration>, rate_limit: RateLimit) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+            buffer: Vec::new(),
+            timeout,
+            limiter: RateLimiter::new(rate_limit),
+            poisoned: false,
+        }
+    }
+
+    async fn send_recv<C: Serialize, D: DeserializeOwned>(&mut self, cmd: &C) -> Result<D> {
+        acquire(&mut self.limiter).await;
+        self.exchange(cmd).await
+    }
+
+    async fn exchange<C: Serialize, D: DeserializeOwned>(&mut self, cmd: &C) -> Result<D> {
+        if self.poisoned {
+            return Err(disconnected());
+        }
+        let message = serde_json::to_vec(cmd).map_err(ErrorKind::JsonError)?;
+        self.poisoned = true;
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(&message)
+            .await
+            .map_err(ErrorKind::Disconnected)?;
+        stream.flush().await.map_err(ErrorKind::Disconnected)?;
+
+        let read = next_line(&mut self.stream, &mut self.buffer);
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, read)
+                .await
+                .map_err(|_| ErrorKind::Timeout)??,
+            None => read.await?,
+        }
+        self.poisoned = false;
+        take_message(&mut self.buffer)
+    }
+
+    async fn call<C: Serialize, D: DeserializeOwned, T>(
+        &mut self,
+        call: Call<C, D, T>,
+    ) -> Result<T> {
+        let response: Response<D> = self.send_recv(&call.command).await?;
+        call.decode(response)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        match self.stream.get_mut().shutdown().await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotConnected => {
+                Err(ErrorKind::Disconnected(e))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The client side of the streaming connection. Commands are queued for the
+/// writer task, so subscriptions can be stopped from `Drop`.
+struct StreamingQueue {
+    /// `None` closes the connection.
+    commands: flume::Sender<Option<StreamingCommandType>>,
+    active: ActiveStreams,
+}
+
+impl StreamingQueue {
+    fn send(&self, command: StreamingCommandType) -> Result<()> {
+        self.commands
+            .send(Some(command))
+            .map_err(|_| disconnected())
+    }
+
+    fn subscribe(
+        self: &Arc<Self>,
+        kind: StreamKind,
+        command: StreamingCommandType,
+    ) -> Result<Subscription> {
+        // Marked active first, so nothing sent right after the command gets dropped
+        self.active.acquire(kind.clone());
+        if let Err(e) = self.send(command) {
+            self.active.release(&kind);
+            return Err(e);
+        }
+        Ok(Subscription::new(kind, self.clone()))
+    }
+
+    /// Every stop command is queued, even if an earlier one failed.
+    fn stop_all(&self) -> Result<()> {
+        let mut result = Ok(());
+        for kind in self.active.drain() {
+            let sent = self.send(kind.stop_command());
+            result = result.and(sent);
+        }
+        result
+    }
+}
+
+impl StreamRelease for StreamingQueue {
+    fn release(&self, kind: &StreamKind) -> Result<()> {
+        if self.active.release(kind) {
+            self.send(kind.stop_command())?;
+        }
+        Ok(())
+    }
+}
+
+async fn write_commands(
+    mut writer: WriteHalf<Tls>,
+    commands: flume::Receiver<Option<StreamingCommandType>>,
+    session_id: String,
+    rate_limit: RateLimit,
+) {
+    let mut limiter = RateLimiter::new(rate_limit);
+    while let Ok(Some(command)) = commands.recv_async().await {
+        acquire(&mut limiter).await;
+        let cmd = StreamingCommand::new(command, session_id.clone());
+        let message = match serde_json::to_vec(&cmd) {
+            Ok(message) => message,
+            Err(e) => {
+                log::warn!("Failed to serialize streaming command: {:?}", e);
+                continue;
+            }
+        };
+        let written = match writer.write_all(&message).await {
+            Ok(()) => writer.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            log::warn!("Streaming connection closed: {:?}", e);
+            return;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+async fn read_messages<R: AsyncBufRead + Unpin>(
+    mut reader: R,
+    streaming: Arc<StreamingQueue>,
+    sender: flume::Sender<StreamingMessage>,
+    keep_alive_timeout: Duration,
+) {
+    let mut buffer = Vec::new();
+    let mut last_keep_alive = Instant::now();
+    let mut stale = false;
+    loop {
+        let read = next_line(&mut reader, &mut buffer);
+        let line = if stale {
+            read.await
+        } else {
+            let wait = keep_alive_timeout.saturating_sub(last_keep_alive.elapsed());
+            match tokio::time::timeout(wait, read).await {
+                Ok(line) => line,
+                Err(_) => {
+                    if streaming.active.contains(&StreamKind::KeepAlive) {
+                        stale = true;
+                        let since = last_keep_alive.elapsed();
+                        let _ = sender.send(StreamingMessage::ConnectionStale { since });
+                    } else {
+                        last_keep_alive = Instant::now();
+                    }
+                    continue;
+                }
+            }
+        };
+        if let Err(e) = line {
+            log::warn!("Streaming connection closed: {:?}", e);
+            return;
+        }
+
+        let msg: StreamingMessage = match take_message(&mut buffer) {
+            Ok(msg) => msg,
+            Err(e) => {
+                log::warn!("Skipping streaming message: {:?}", e);
+                continue;
+            }
+        };
+        if let StreamingMessage::KeepAlive { .. } = msg {
+            last_keep_alive = Instant::now();
+            stale = false;
+        }
+        // Messages can still arrive for a short while after a stream was stopped
+        let active = match msg.kind() {
+            Some(kind) => streaming.active.contains(&kind),
+            None => true,
+        };
+        if active && sender.send(msg).is_err() {
+            return;
+        }
+    }
+}
+
+async fn heartbeat(
+    requests: Arc<Mutex<RequestConnection>>,
+    streaming: Arc<StreamingQueue>,
+    intervals: flume::Receiver<Duration>,
+    mut interval: Duration,
+) {
+    loop {
+        match tokio::time::timeout(interval, intervals.recv_async()).await {
+            Ok(Ok(new_interval)) => {
+                interval = new_interval;
+                continue;
+            }
+            Ok(Err(_)) => return,
+            Err(_) => {}
+        }
+
+        if let Err(e) = requests.lock().await.call(calls::ping()).await {
+            log::warn!("Request connection ping failed: {:?}", e);
+        }
+
+        if let Err(e) = streaming.send(StreamingCommandType::Ping) {
+            log::warn!("Streaming connection ping failed: {:?}", e);
+        }
+    }
+}
+
+/// The non-blocking counterpart of `XTB`, for use inside a tokio runtime.
+///
+/// Requests take `&self`, so one client can be shared between tasks behind an
+/// `Arc`. They are still sent one at a time over the request connection.
+/// A request that times out or is cancelled before its reply arrives leaves
+/// that connection unusable, later requests fail with `ErrorKind::Disconnected`.
+/// Only `TransportKind::Tls` is supported.
+///
+/// The requests are the same as `XTB`'s, except that:
+/// - there is no `refresh_account_info`, `account_info` is fixed at login,
+/// - `messages` is a `Stream` and `next_message` replaces `handle_messages`
+///   and `handle_messages_blocking`,
+/// - `history` fetches the whole range before returning, see `history_with_window`,
+/// - `clock` returns a copy of the last measurement.
+pub struct AsyncXTB {
+    config: XtbConfig,
+    requests: Arc<Mutex<RequestConnection>>,
+    stream: flume::Receiver<StreamingMessage>,
+    streaming: Arc<StreamingQueue>,
+    reader: JoinHandle<()>,
+    writer: Option<JoinHandle<()>>,
+    heartbeat: Option<(flume::Sender<Duration>, JoinHandle<()>)>,
+    _keep_alive: Subscription,
+    logged_out: AtomicBool,
+    /// Spaces out orders on top of the connection's own limit, so requests
+    /// made by the client itself don't use up the budget for orders.
+    orders: std::sync::Mutex<RateLimiter>,
+    // These and `orders` are never locked across an `.await`
+    clock: std::sync::Mutex<Option<ServerClock>>,
+    step_rules: std::sync::Mutex<Option<Arc<StepRules>>>,
+    market_hours: std::sync::Mutex<Option<TradingCalendar>>,
+    account: UserDataRecord,
+    api_version: String,
+}
+
+impl AsyncXTB {
+    /// Connects to the demo servers with the default settings.
+    pub async fn connect(user_id: usize, password: &str) -> Result<Self> {
+        Self::connect_with(XtbConfig::demo(), user_id, password).await
+    }
+
+    pub async fn connect_with(config: XtbConfig, user_id: usize, password: &str) -> Result<Self> {
+        if config.transport != TransportKind::Tls {
+            return Err(ErrorKind::InvalidConfig(
+                "the async client only supports TransportKind::Tls".to_owned(),
+            ));
+        }
+
+        let socket =
+            connect_tls(&config.host, config.request_port(), config.connect_timeout).await?;
+        let mut requests =
+            RequestConnection::new(socket, config.request_timeout, config.rate_limit);
+
+        let streaming_id = requests
+            .call(calls::login(user_id, password, &config.app_name))
+            .await?;
+        let account = requests.call(calls::current_user_data()).await?;
+        let api_version = requests.call(calls::version()).await?;
+
+        let socket = connect_tls(
+            &config.host,
+            config.streaming_port(),
+            config.connect_timeout,
+        )
+        .await?;
+        let (read_half, write_half) = tokio::io::split(socket);
+
+        let (command_sender, command_receiver) = flume::unbounded();
+        let streaming = Arc::new(StreamingQueue {
+            commands: command_sender,
+            active: ActiveStreams::default(),
+        });
+        let writer = tokio::spawn(write_commands(
+            write_half,
+            command_receiver,
+            streaming_id,
+            config.rate_limit,
+        ));
+
+        let (stream_sender, stream_receiver) = flume::unbounded();
+        let reader = tokio::spawn(read_messages(
+            BufReader::new(read_half),
+            streaming.clone(),
+            stream_sender,
+            KEEP_ALIVE_TIMEOUT,
+        ));
+
+        let keep_alive =
+            streaming.subscribe(StreamKind::KeepAlive, StreamingCommandType::GetKeepAlive)?;
+        let requests = Arc::new(Mutex::new(requests));
+        let (interval_sender, interval_receiver) = flume::unbounded();
+        let heartbeat = tokio::spawn(heartbeat(
+            requests.clone(),
+            streaming.clone(),
+            interval_receiver,
+            config.heartbeat_interval,
+        ));
+
+        let orders = RateLimiter::new(config.rate_limit);
+        Ok(Self {
+            config,
+            requests,
+            stream: stream_receiver,
+            streaming,
+            reader,
+            writer: Some(writer),
+            heartbeat: Some((interval_sender, heartbeat)),
+            _keep_alive: keep_alive,
+            logged_out: AtomicBool::new(false),
+            orders: std::sync::Mutex::new(orders),
+            clock: std::sync::Mutex::new(None),
+            step_rules: std::sync::Mutex::new(None),
+            market_hours: std::sync::Mutex::new(None),
+            account,
+            api_version,
+        })
+    }
+
+    pub fn start_balance(&self) -> Result<Subscription> {
+        self.streaming
+            .subscribe(StreamKind::Balance, StreamingCommandType::GetBalance)
+    }
+
+    pub fn start_trades(&self) -> Result<Subscription> {
+        self.streaming
+            .subscribe(StreamKind::Trades, StreamingCommandType::GetTrades)
+    }
+
+    pub fn start_trade_status(&self) -> Result<Subscription> {
+        self.streaming.subscribe(
+            StreamKind::TradeStatus,
+            StreamingCommandType::GetTradeStatus,
+        )
+    }
+
+    pub fn subscribe_profits(&self) -> Result<Subscription> {
+        self.streaming
+            .subscribe(StreamKind::Profits, StreamingCommandType::GetProfits)
+    }
+
+    pub fn subscribe_candles<S: Into<Symbol>>(&self, symbol: S) -> Result<Subscription> {
+        let symbol = symbol.into();
+        self.streaming.subscribe(
+            StreamKind::Candles(symbol.0.clone()),
+            StreamingCommandType::GetCandles { symbol: symbol.0 },
+        )
+    }
+
+    pub fn subscribe_news(&self) -> Result<Subscription> {
+        self.streaming
+            .subscribe(StreamKind::News, StreamingCommandType::GetNews)
+    }
+
+    pub fn subscribe_ticks<S: Into<Symbol>>(
+        &self,
+        symbol: S,
+        options: TickOptions,
+    ) -> Result<Subscription> {
+        let symbol = symbol.into();
+        self.streaming.subscribe(
+            StreamKind::TickPrices(symbol.0.clone()),
+            StreamingCommandType::GetTickPrices {
+                symbol: symbol.0,
+                min_arrival_time: options.min_arrival_time,
+                max_level: options.max_level,
+            },
+        )
+    }
+
+    /// Streams that currently have at least one live `Subscription`.
+    pub fn active_subscriptions(&self) -> Vec<StreamKind> {
+        self.streaming.active.kinds()
+    }
+
+    /// Messages from the streaming connection. Ends when the connection is closed.
+    pub fn messages(&self) -> impl Stream<Item = StreamingMessage> + '_ {
+        self.stream.stream()
+    }
+
+    /// The next streaming message, `None` once the connection is closed.
+    pub async fn next_message(&self) -> Option<StreamingMessage> {
+        self.stream.recv_async().await.ok()
+    }
+
+    pub fn config(&self) -> &XtbConfig {
+        &self.config
+    }
+
+    pub fn environment(&self) -> Environment {
+        self.config.environment
+    }
+
+    /// Account details, as fetched right after logging in.
+    pub fn account_info(&self) -> &UserDataRecord {
+        &self.account
+    }
+
+    pub fn api_version(&self) -> &str {
+        &self.api_version
+    }
+
+    async fn call<C: Serialize, D: DeserializeOwned, T>(&self, call: Call<C, D, T>) -> Result<T> {
+        self.requests.lock().await.call(call).await
+    }
+
+    pub async fn ping(&self) -> Result<()> {
+        self.call(calls::ping()).await
+    }
+
+    /// Changes how often both connections are pinged in the background.
+    pub fn set_heartbeat_interval(&self, interval: Duration) {
+        if let Some((sender, _)) = &self.heartbeat {
+            let _ = sender.send(interval);
+        }
+    }
+
+    /// Ends the session. The server closes both connections afterwards.
+    pub async fn logout(&self) -> Result<()> {
+        if self.logged_out.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        // The session is over even if the reply says otherwise
+        let call = calls::logout();
+        let response = self.requests.lock().await.send_recv(&call.command).await?;
+        self.logged_out.store(true, Ordering::SeqCst);
+        call.decode(response)
+    }
+
+    /// Stops all streams, logs out and closes both connections.
+    ///
+    /// Dropping the client only closes the connections, without logging out.
+    pub async fn shutdown(mut self) -> Result<()> {
+        if let Some((sender, heartbeat)) = self.heartbeat.take() {
+            drop(sender);
+            let _ = heartbeat.await;
+        }
+
+        // Keep going on errors, everything should get torn down anyway
+        let stopped = self.streaming.stop_all();
+        let logged_out = self.logout().await;
+
+        let _ = self.streaming.commands.send(None);
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.await;
+        }
+        self.reader.abort();
+        let closed = self.requests.lock().await.close().await;
+
+        stopped.and(logged_out).and(closed)
+    }
+
+    pub async fn send_transaction(&self, transaction: Transaction<'_>) -> Result<OrderId> {
+        loop {
+            let acquired = self.orders.lock().unwrap().try_acquire();
+            match acquired {
+                Ok(()) => break,
+                Err(wait) if !self.config.queue_orders => return Err(ErrorKind::RateLimited(wait)),
+                Err(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+        self.call(calls::send_transaction(transaction)).await
+    }
+
+    pub async fn transaction_status(&self, order: OrderId) -> Result<TransactionStatus> {
+        self.call(calls::transaction_status(order)).await
+    }
+
+    /// Sends a transaction and polls its status until the server accepts or rejects it.
+    ///
+    /// Fails with `ErrorKind::PendingTimeout`, holding the last status and with it
+    /// the order id, if the order is still pending when `timeout` runs out.
+    pub async fn send_transaction_and_wait(
+        &self,
+        transaction: Transaction<'_>,
+        timeout: Duration,
+    ) -> Result<TransactionStatus> {
+        let deadline = Instant::now() + timeout;
+        let order = self.send_transaction(transaction).await?;
+        loop {
+            let status = self.transaction_status(order).await?;
+            if status.request_status.is_final() {
+                return Ok(status);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(ErrorKind::PendingTimeout(status));
+            }
+            tokio::time::sleep(left.min(TRANSACTION_STATUS_POLL_INTERVAL)).await;
+        }
+    }
+
+    /// Currently open positions.
+    pub async fn open_trades(&self) -> Result<Vec<TradeRecord>> {
+        self.call(calls::trades(true)).await
+    }
+
+    /// Like `open_trades`, without restricting the result to opened trades.
+    pub async fn all_trades(&self) -> Result<Vec<TradeRecord>> {
+        self.call(calls::trades(false)).await
+    }
+
+    pub async fn trade_records(&self, orders: &[OrderId]) -> Result<Vec<TradeRecord>> {
+        self.call(calls::trade_records(orders)).await
+    }
+
+    pub async fn news(&self, range: std::ops::Range<DateTime<Utc>>) -> Result<Vec<NewsRecord>> {
+        self.call(calls::news(range.start, range.end)).await
+    }
+
+    pub async fn ib_history(&self, range: std::ops::Range<DateTime<Utc>>) -> Result<Vec<IbRecord>> {
+        self.call(calls::ib_history(range.start, range.end)).await
+    }
+
+    /// Trades closed in `range`, fetched a week at a time.
+    pub async fn history(&self, range: std::ops::Range<DateTime<Utc>>) -> Result<Vec<TradeRecord>> {
+        self.fetch_history(Windows::new(range)).await
+    }
+
+    /// Like `history`, requesting `window` at once, see `TradeHistory::window`.
+    ///
+    /// # Panics
+    ///
+    /// If `window` is zero or negative.
+    pub async fn history_with_window(
+        &self,
+        range: std::ops::Range<DateTime<Utc>>,
+        window: chrono::Duration,
+    ) -> Result<Vec<TradeRecord>> {
+        let mut windows = Windows::new(range);
+        windows.set_window(window);
+        self.fetch_history(windows).await
+    }
+
+    async fn fetch_history(&self, mut windows: Windows) -> Result<Vec<TradeRecord>> {
+        let mut trades = Vec::new();
+        while let Some(window) = windows.next_window() {
+            let records = self
+                .call(calls::trades_history(window.start, window.end))
+                .await?;
+            trades.extend(records.into_iter().filter(|record| windows.is_new(record)));
+        }
+        Ok(trades)
+    }
+
+    pub async fn trading_hours(&self, symbols: &[&str]) -> Result<Vec<TradingHoursRecord>> {
+        self.call(calls::trading_hours(symbols)).await
+    }
+
+    pub async fn trading_calendar(&self, symbols: &[&str]) -> Result<TradingCalendar> {
+        Ok(TradingCalendar::new(self.trading_hours(symbols).await?))
+    }
+
+    /// Makes `buy` and `sell` fail with `ErrorKind::MarketClosed` instead of
+    /// sending orders outside of the symbol's trading hours.
+    pub fn check_market_hours(&self, enabled: bool) {
+        let mut market_hours = self.market_hours.lock().unwrap();
+        *market_hours = if enabled {
+            Some(market_hours.take().unwrap_or_default())
+        } else {
+            None
+        };
+    }
+
+    async fn ensure_market_open(&self, symbol: &Symbol) -> Result<()> {
+        let known = match &*self.market_hours.lock().unwrap() {
+            Some(calendar) => calendar.contains(&symbol.0),
+            None => return Ok(()),
+        };
+        if !known {
+            let records = self.trading_hours(&[&symbol.0]).await?;
+            if let Some(calendar) = &mut *self.market_hours.lock().unwrap() {
+                calendar.extend(records);
+            }
+        }
+
+        let now = self.server_now().await?;
+        match &*self.market_hours.lock().unwrap() {
+            Some(calendar) => calendar.ensure_open(&symbol.0, now),
+            // Turned off in the meantime
+            None => Ok(()),
+        }
+    }
+
+    pub async fn buy<S: Into<Symbol>>(&self, symbol: S, volume: f64) -> Result<OrderId> {
+        let symbol = symbol.into();
+        self.ensure_market_open(&symbol).await?;
+        self.send_transaction(symbol.buy(volume)).await
+    }
+
+    pub async fn sell<S: Into<Symbol>>(&self, symbol: S, volume: f64) -> Result<OrderId> {
+        let symbol = symbol.into();
+        self.ensure_market_open(&symbol).await?;
+        self.send_transaction(symbol.sell(volume)).await
+    }
+
+    pub async fn symbols(&self) -> Result<Vec<SymbolRecord>> {
+        self.call(calls::symbols()).await
+    }
+
+    pub async fn symbol<S: Into<Symbol>>(&self, symbol: S) -> Result<SymbolRecord> {
+        self.call(calls::symbol(symbol.into())).await
+    }
+
+    pub async fn candles_range<S: Into<Symbol>>(
+        &self,
+        symbol: S,
+        period: Period,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Candle>> {
+        self.call(calls::candles_range(symbol.into(), period, from, to))
+            .await
+    }
+
+    pub async fn candles_since<S: Into<Symbol>>(
+        &self,
+        symbol: S,
+        period: Period,
+        start: DateTime<Utc>,
+    ) -> Result<Vec<Candle>> {
+        self.call(calls::candles_since(symbol.into(), period, start))
+            .await
+    }
+
+    pub async fn calendar(&self) -> Result<Vec<CalendarRecord>> {
+        self.call(calls::calendar()).await
+    }
+
+    pub async fn commission<S: Into<Symbol>>(
+        &self,
+        symbol: S,
+        volume: f64,
+    ) -> Result<CommissionRecord> {
+        self.call(calls::commission(symbol.into(), volume)).await
+    }
+
+    pub async fn margin_for<S: Into<Symbol>>(
+        &self,
+        symbol: S,
+        volume: f64,
+    ) -> Result<MarginTradeRecord> {
+        self.call(calls::margin_for(symbol.into(), volume)).await
+    }
+
+    pub async fn margin_level(&self) -> Result<MarginLevelRecord> {
+        self.call(calls::margin_level()).await
+    }
+
+    /// Profit of a position, in the account currency, as the server would book it.
+    pub async fn calculate_profit<S: Into<Symbol>>(
+        &self,
+        symbol: S,
+        side: OrderSide,
+        volume: f64,
+        open_price: f64,
+        close_price: f64,
+    ) -> Result<f64> {
+        let symbol = symbol.into();
+        self.call(calls::calculate_profit(
+            symbol,
+            side,
+            volume,
+            open_price,
+            close_price,
+        ))
+        .await
+    }
+
+    pub async fn server_time(&self) -> Result<DateTime<Utc>> {
+        self.call(calls::server_time()).await
+    }
+
+    /// Measures the offset between the local and the server clock.
+    pub async fn sync_clock(&self) -> Result<ServerClock> {
+        let sent = Utc::now();
+        let server_time = self.server_time().await?;
+        let received = Utc::now();
+        let clock = ServerClock::from_sample(sent, received, server_time);
+        *self.clock.lock().unwrap() = Some(clock.clone());
+        Ok(clock)
+    }
+
+    /// The last clock measurement, if any.
+    pub fn clock(&self) -> Option<ServerClock> {
+        self.clock.lock().unwrap().clone()
+    }
+
+    /// Current server time, re-measuring the clock offset when it gets old.
+    pub async fn server_now(&self) -> Result<DateTime<Utc>> {
+        match self.clock() {
+            Some(clock) if clock.is_fresh() => Ok(clock.now()),
+            _ => Ok(self.sync_clock().await?.now()),
+        }
+    }
+
+    pub async fn step_rules(&self) -> Result<Vec<StepRule>> {
+        self.call(calls::step_rules()).await
+    }
+
+    /// Builds a rounder for `symbol`. Step rules are fetched once and then cached.
+    pub async fn price_rounder(&self, symbol: &SymbolRecord) -> Result<PriceRounder> {
+        let cached = self.step_rules.lock().unwrap().clone();
+        let rules = match cached {
+            Some(rules) => rules,
+            None => {
+                let rules = Arc::new(StepRules::new(self.step_rules().await?));
+                *self.step_rules.lock().unwrap() = Some(rules.clone());
+                rules
+            }
+        };
+        Ok(rules.rounder(symbol))
+    }
+
+    /// Rounds the prices of `transaction` to its symbol's price steps.
+    pub async fn round_prices(&self, transaction: &mut Transaction<'_>) -> Result<()> {
+        let record = self.symbol(transaction.symbol.unwrap_or_default()).await?;
+        let rounder = self.price_rounder(&record).await?;
+        transaction.round_prices(&rounder);
+        Ok(())
+    }
+
+    async fn send_pending(&self, mut transaction: Transaction<'_>) -> Result<OrderId> {
+        let record = self.symbol(transaction.symbol.unwrap_or_default()).await?;
+        let rounder = self.price_rounder(&record).await?;
+        transaction.round_prices(&rounder);
+        transaction.check_market(record.bid, record.ask)?;
+        self.send_transaction(transaction).await
+    }
+
+    pub async fn place_limit<S: Into<Symbol>>(
+        &self,
+        symbol: S,
+        side: OrderSide,
+        volume: f64,
+        price: f64,
+        options: PendingOptions,
+    ) -> Result<OrderId> {
+        let symbol = symbol.into();
+        self.send_pending(symbol.pending(side.limit(), volume, price, &options)?)
+            .await
+    }
+
+    pub async fn place_stop<S: Into<Symbol>>(
+        &self,
+        symbol: S,
+        side: OrderSide,
+        volume: f64,
+        price: f64,
+        options: PendingOptions,
+    ) -> Result<OrderId> {
+        let symbol = symbol.into();
+        self.send_pending(symbol.pending(side.stop(), volume, price, &options)?)
+            .await
+    }
+
+    pub async fn close<S: Into<Symbol>>(
+        &self,
+        symbol: S,
+        order_id: PositionId,
+        volume: f64,
+    ) -> Result<()> {
+        self.send_transaction(symbol.into().close(order_id, volume))
+            .await?;
+        Ok(())
+    }
+}
+
+impl Drop for AsyncXTB {
+    fn drop(&mut self) {
+        if let Some((_, heartbeat)) = self.heartbeat.take() {
+            heartbeat.abort();
+        }
+        self.reader.abort();
+        // Lets the writer task close the streaming connection
+        let _ = self.streaming.commands.send(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// Only has to compile: requests must be usable from `tokio::spawn`.
+    #[allow(dead_code)]
+    fn requests_are_send(client: &AsyncXTB) {
+        fn send<T: Send>(_: T) {}
+        send(client.buy("EURUSD", 0.1));
+        send(client.history(Utc::now()..Utc::now()));
+        send(client.place_limit(
+            "EURUSD",
+            OrderSide::Buy,
+            0.1,
+            1.0,
+            PendingOptions::default(),
+        ));
+    }
+
+    const KEEP_ALIVE: &[u8] = b"{\"command\":\"keepAlive\",\"data\":{\"timestamp\":1}}\n\n";
+
+    #[test]
+    fn next_line_skips_blank_lines() {
+        block_on(async {
+            let mut reader: &[u8] = b"{\"a\":1}\n\n\n{\"a\":2}\n\n";
+            let mut buffer = Vec::new();
+            for expected in 1..=2 {
+                next_line(&mut reader, &mut buffer).await.unwrap();
+                let message: serde_json::Value = take_message(&mut buffer).unwrap();
+                assert_eq!(message["a"], expected);
+            }
+            assert!(next_line(&mut reader, &mut buffer).await.is_err());
+        });
+    }
+
+    #[test]
+    fn next_line_resumes_after_being_cancelled() {
+        block_on(async {
+            let (client, mut server) = tokio::io::duplex(1024);
+            let mut reader = BufReader::new(client);
+            let mut buffer = Vec::new();
+
+            server.write_all(b"{\"a\":").await.unwrap();
+            let read = next_line(&mut reader, &mut buffer);
+            assert!(tokio::time::timeout(Duration::from_millis(50), read)
+                .await
+                .is_err());
+
+            server.write_all(b"1}\n\n").await.unwrap();
+            next_line(&mut reader, &mut buffer).await.unwrap();
+            let message: serde_json::Value = take_message(&mut buffer).unwrap();
+            assert_eq!(message["a"], 1);
+        });
+    }
+
+    #[test]
+    fn request_connection_is_unusable_after_a_timeout() {
+        block_on(async {
+            let (client, mut server) = tokio::io::duplex(1024);
+            let unlimited = RateLimit::new(Duration::ZERO, 1);
+            let ping = SimpleCommand::ping();
+            let mut requests =
+                RequestConnection::new(client, Some(Duration::from_millis(50)), unlimited);
+
+            let reply = requests.send_recv::<_, Response<()>>(&ping);
+            assert!(matches!(reply.await, Err(ErrorKind::Timeout)));
+
+            // The late reply must not be taken for the answer to the next request
+            server.write_all(b"{\"status\":true}\n\n").await.unwrap();
+            let reply = requests.send_recv::<_, Response<()>>(&ping);
+            assert!(matches!(reply.await, Err(ErrorKind::Disconnected(_))));
+        });
+    }
+
+    #[test]
+    fn request_connection_is_unusable_after_being_cancelled() {
+        block_on(async {
+            let (client, mut server) = tokio::io::duplex(1024);
+            let unlimited = RateLimit::new(Duration::ZERO, 1);
+            let ping = SimpleCommand::ping();
+            let mut requests = RequestConnection::new(client, None, unlimited);
+
+            server.write_all(b"{\"status\":true}\n\n").await.unwrap();
+            let reply = requests.send_recv::<_, Response<()>>(&ping);
+            reply.await.unwrap().into_empty_result().unwrap();
+
+            let reply = requests.send_recv::<_, Response<()>>(&ping);
+            assert!(tokio::time::timeout(Duration::from_millis(50), reply)
+                .await
+                .is_err());
+
+            server.write_all(b"{\"status\":true}\n\n").await.unwrap();
+            let reply = requests.send_recv::<_, Response<()>>(&ping);
+            assert!(matches!(reply.await, Err(ErrorKind::Disconnected(_))));
+        });
+    }
+
+    #[test]
+    fn reader_filters_messages_and_reports_stale_keep_alives() {
+        block_on(async {
+            let (client, mut server) = tokio::io::duplex(4096);
+            let (commands, _command_receiver) = flume::unbounded();
+            let streaming = Arc::new(StreamingQueue {
+                commands,
+                active: ActiveStreams::default(),
+            });
+            streaming.active.acquire(StreamKind::KeepAlive);
+            let (sender, receiver) = flume::unbounded();
+            let timeout = Duration::from_millis(100);
+            tokio::spawn(read_messages(
+                BufReader::new(client),
+                streaming,
+                sender,
+                timeout,
+            ));
+            let next = || tokio::time::timeout(Duration::from_secs(1), receiver.recv_async());
+
+            server.write_all(KEEP_ALIVE).await.unwrap();
+            let msg = next().await.unwrap().unwrap();
+            assert!(matches!(msg, StreamingMessage::KeepAlive { .. }));
+
+            let msg = next().await.unwrap().unwrap();
+            match msg {
+                StreamingMessage::ConnectionStale { since } => assert!(since >= timeout),
+                other => panic!("expected ConnectionStale, got {:?}", other),
+            }
+            // Reported once, not on every timeout
+            tokio::time::sleep(timeout * 3).await;
+            assert!(receiver.is_empty());
+
+            // Unparseable and inactive messages are skipped, the reader keeps going
+            server.write_all(b"{\"status\":true}\n\n").await.unwrap();
+            server
+                .write_all(b"{\"command\":\"balance\",\"data\":{\"balance\":1.0,\"credit\":0.0,\"equity\":1.0,\"margin\":0.0,\"marginFree\":1.0,\"marginLevel\":0.0}}\n\n")
+                .await
+                .unwrap();
+            server.write_all(KEEP_ALIVE).await.unwrap();
+            let msg = next().await.unwrap().unwrap();
+            assert!(matches!(msg, StreamingMessage::KeepAlive { .. }));
+
+            // A fresh keep-alive re-arms the stale check
+            let msg = next().await.unwrap().unwrap();
+            assert!(matches!(msg, StreamingMessage::ConnectionStale { .. }));
+
+            drop(server);
+            assert!(next().await.unwrap().is_err());
+        });
+    }
+}
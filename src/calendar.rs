@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Impact {
+    #[serde(rename = "1")]
+    Low,
+    #[serde(rename = "2")]
+    Medium,
+    #[serde(rename = "3")]
+    High,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CalendarRecord {
+    pub country: String,
+    pub current: String,
+    pub forecast: String,
+    pub impact: Impact,
+    pub period: String,
+    pub previous: String,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub time: DateTime<Utc>,
+    pub title: String,
+}
+
+/// Selects calendar events by country, impact and time.
+#[derive(Default, Clone, Debug)]
+pub struct CalendarFilter {
+    countries: Vec<String>,
+    min_impact: Option<Impact>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+impl CalendarFilter {
+    /// Keeps events of `country`. Can be called several times to allow more countries.
+    pub fn country(mut self, country: &str) -> Self {
+        self.countries.push(country.to_owned());
+        self
+    }
+
+    pub fn min_impact(mut self, impact: Impact) -> Self {
+        self.min_impact = Some(impact);
+        self
+    }
+
+    /// Keeps events in `[from, to)`.
+    pub fn between(mut self, from: DateTime<Utc>, to: DateTime<Utc>) -> Self {
+        self.from = Some(from);
+        self.to = Some(to);
+        self
+    }
+
+    pub fn matches(&self, record: &CalendarRecord) -> bool {
+        let country = self.countries.is_empty()
+            || self
+                .countries
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(&record.country));
+        let impact = self.min_impact.is_none_or(|min| record.impact >= min);
+        let from = self.from.is_none_or(|from| record.time >= from);
+        let to = self.to.is_none_or(|to| record.time < to);
+        country && impact && from && to
+    }
+
+    pub fn apply(&self, records: Vec<CalendarRecord>) -> Vec<CalendarRecord> {
+        records.into_iter().filter(|r| self.matches(r)).collect()
+    }
+}
@@ -0,0 +1,199 @@
+//! Requests shared by `XTB` and `AsyncXTB`: the command to send and what to
+//! make of the reply. The clients only differ in how they get it across.
+
+use chrono::{DateTime, Utc};
+
+use crate::commands::*;
+use crate::types::*;
+use crate::{CalendarRecord, Result, ServerTimeRecord, StepRule, TradingHoursRecord, Transaction};
+
+/// A command together with the conversion of its `Response` into the result.
+pub(crate) struct Call<C, D, T = D> {
+    pub(crate) command: C,
+    decode: fn(Response<D>) -> Result<T>,
+}
+
+impl<C, D> Call<C, D> {
+    fn new(command: C) -> Self {
+        Self {
+            command,
+            decode: Response::into_result,
+        }
+    }
+}
+
+impl<C, D, T> Call<C, D, T> {
+    fn with(command: C, decode: fn(Response<D>) -> Result<T>) -> Self {
+        Self { command, decode }
+    }
+
+    pub(crate) fn decode(&self, response: Response<D>) -> Result<T> {
+        (self.decode)(response)
+    }
+}
+
+type Simple<D, T = D> = Call<SimpleCommand, D, T>;
+
+/// Answers with the streaming session id.
+pub(crate) fn login<'a>(
+    user_id: usize,
+    password: &'a str,
+    app_name: &'a str,
+) -> Call<LoginCommand<'a>, (), String> {
+    Call::with(
+        LoginCommand::new(user_id, password, app_name),
+        Response::into_stream_session_id,
+    )
+}
+
+pub(crate) fn logout() -> Simple<()> {
+    Call::with(SimpleCommand::logout(), Response::into_empty_result)
+}
+
+pub(crate) fn ping() -> Simple<()> {
+    Call::with(SimpleCommand::ping(), Response::into_empty_result)
+}
+
+pub(crate) fn current_user_data() -> Simple<UserDataRecord> {
+    Call::new(SimpleCommand::get_current_user_data())
+}
+
+pub(crate) fn version() -> Simple<VersionResponse, String> {
+    Call::with(SimpleCommand::get_version(), |response| {
+        Ok(response.into_result()?.version)
+    })
+}
+
+pub(crate) fn send_transaction(
+    transaction: Transaction,
+) -> Call<TradeTransactionCommand, TradeTransactionResponse, OrderId> {
+    Call::with(TradeTransactionCommand::new(transaction), |response| {
+        Ok(response.into_result()?.order)
+    })
+}
+
+pub(crate) fn transaction_status(
+    order: OrderId,
+) -> Call<TradeTransactionStatusCommand, TransactionStatus> {
+    Call::new(TradeTransactionStatusCommand::new(order))
+}
+
+pub(crate) fn trades(opened_only: bool) -> Call<GetTradesCommand, Vec<TradeRecord>> {
+    Call::new(GetTradesCommand::new(opened_only))
+}
+
+pub(crate) fn trade_records(orders: &[OrderId]) -> Call<GetTradeRecordsCommand, Vec<TradeRecord>> {
+    Call::new(GetTradeRecordsCommand::new(orders.to_vec()))
+}
+
+pub(crate) fn trades_history(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Call<GetTradesHistoryCommand, Vec<TradeRecord>> {
+    Call::new(GetTradesHistoryCommand::new(start, end))
+}
+
+pub(crate) fn news(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Call<GetNewsCommand, Vec<NewsRecord>> {
+    Call::new(GetNewsCommand::new(start, end))
+}
+
+pub(crate) fn ib_history(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Call<GetIbsHistoryCommand, Vec<IbRecord>> {
+    Call::new(GetIbsHistoryCommand::new(start, end))
+}
+
+pub(crate) fn trading_hours(
+    symbols: &[&str],
+) -> Call<GetTradingHoursCommand, Vec<TradingHoursRecord>> {
+    let symbols = symbols.iter().map(|s| s.to_string()).collect();
+    Call::new(GetTradingHoursCommand::new(symbols))
+}
+
+pub(crate) fn symbols() -> Simple<Vec<SymbolRecord>> {
+    Call::new(SimpleCommand::get_all_symbols())
+}
+
+pub(crate) fn symbol(symbol: Symbol) -> Call<GetSymbolCommand, SymbolRecord> {
+    Call::new(GetSymbolCommand::new(symbol.0))
+}
+
+pub(crate) fn candles_range(
+    symbol: Symbol,
+    period: Period,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Call<GetChartRangeCommand, ChartResponse, Vec<Candle>> {
+    let info = ChartRangeInfo {
+        end: to,
+        period,
+        start: from,
+        symbol: symbol.0,
+        ticks: 0,
+    };
+    Call::with(GetChartRangeCommand::new(info), |response| {
+        Ok(response.into_result()?.candles())
+    })
+}
+
+pub(crate) fn candles_since(
+    symbol: Symbol,
+    period: Period,
+    start: DateTime<Utc>,
+) -> Call<GetChartLastCommand, ChartResponse, Vec<Candle>> {
+    let info = ChartLastInfo {
+        period,
+        start,
+        symbol: symbol.0,
+    };
+    Call::with(GetChartLastCommand::new(info), |response| {
+        Ok(response.into_result()?.candles())
+    })
+}
+
+pub(crate) fn calendar() -> Simple<Vec<CalendarRecord>> {
+    Call::new(SimpleCommand::get_calendar())
+}
+
+pub(crate) fn commission(
+    symbol: Symbol,
+    volume: f64,
+) -> Call<GetCommissionDefCommand, CommissionRecord> {
+    Call::new(GetCommissionDefCommand::new(symbol.0, volume))
+}
+
+pub(crate) fn margin_for(
+    symbol: Symbol,
+    volume: f64,
+) -> Call<GetMarginTradeCommand, MarginTradeRecord> {
+    Call::new(GetMarginTradeCommand::new(symbol.0, volume))
+}
+
+pub(crate) fn margin_level() -> Simple<MarginLevelRecord> {
+    Call::new(SimpleCommand::get_margin_level())
+}
+
+pub(crate) fn calculate_profit(
+    symbol: Symbol,
+    side: OrderSide,
+    volume: f64,
+    open_price: f64,
+    close_price: f64,
+) -> Call<GetProfitCalculationCommand, ProfitCalculationResponse, f64> {
+    let cmd = GetProfitCalculationCommand::new(symbol.0, side, volume, open_price, close_price);
+    Call::with(cmd, |response| Ok(response.into_result()?.profit))
+}
+
+pub(crate) fn server_time() -> Simple<ServerTimeRecord, DateTime<Utc>> {
+    Call::with(SimpleCommand::get_server_time(), |response| {
+        Ok(response.into_result()?.time)
+    })
+}
+
+pub(crate) fn step_rules() -> Simple<Vec<StepRule>> {
+    Call::new(SimpleCommand::get_step_rules())
+}
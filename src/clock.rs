@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ServerTimeRecord {
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub time: DateTime<Utc>,
+}
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Offset between the local clock and the server's, estimated from a single round trip.
+#[derive(Clone, Debug)]
+pub struct ServerClock {
+    offset: chrono::Duration,
+    round_trip: Duration,
+    sampled_at: Instant,
+}
+
+impl ServerClock {
+    /// Assumes the server read its clock halfway through the round trip.
+    pub(crate) fn from_sample(
+        sent: DateTime<Utc>,
+        received: DateTime<Utc>,
+        server_time: DateTime<Utc>,
+    ) -> Self {
+        let round_trip = received - sent;
+        let local_midpoint = sent + round_trip / 2;
+        Self {
+            offset: server_time - local_midpoint,
+            round_trip: round_trip.to_std().unwrap_or_default(),
+            sampled_at: Instant::now(),
+        }
+    }
+
+    /// How far the server clock is ahead of the local one.
+    pub fn offset(&self) -> chrono::Duration {
+        self.offset
+    }
+
+    /// Round trip of the sample, the offset is accurate to about half of it.
+    pub fn round_trip(&self) -> Duration {
+        self.round_trip
+    }
+
+    pub fn age(&self) -> Duration {
+        self.sampled_at.elapsed()
+    }
+
+    /// Whether the offset is recent enough to not be measured again.
+    pub(crate) fn is_fresh(&self) -> bool {
+        self.age() < REFRESH_INTERVAL
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        Utc::now() + self.offset
+    }
+
+    pub fn to_server(&self, local: DateTime<Utc>) -> DateTime<Utc> {
+        local + self.offset
+    }
+
+    pub fn to_local(&self, server: DateTime<Utc>) -> DateTime<Utc> {
+        server - self.offset
+    }
+}
@@ -1,9 +1,11 @@
 #![feature(concat_idents)]
+use chrono::{DateTime, Utc};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 use crate::transaction::*;
 use crate::types::*;
+use crate::{ErrorKind, Result};
 //use casey::pascal;
 
 pub trait Command {}
@@ -39,18 +41,189 @@ pub enum Response<D> {
         #[serde(rename = "streamSessionId")]
         stream_session_id: String,
     },
+    Empty {
+        status: bool,
+    },
 }
 
 impl<D> CResponse for Response<D> {}
 
+impl<D> Response<D> {
+    pub(crate) fn into_result(self) -> Result<D> {
+        match self {
+            Response::Success { return_data, .. } => Ok(return_data),
+            Response::Error {
+                error_code,
+                error_desc,
+                ..
+            } => Err(ErrorKind::ApiError(error_code, error_desc)),
+            _ => Err(ErrorKind::InvalidResponse),
+        }
+    }
+}
+
+impl Response<()> {
+    /// For commands that only answer with a status.
+    pub(crate) fn into_empty_result(self) -> Result<()> {
+        match self {
+            Response::Empty { status: true } | Response::Success { status: true, .. } => Ok(()),
+            other => other.into_result(),
+        }
+    }
+
+    /// The stream session id from the answer to a login.
+    pub(crate) fn into_stream_session_id(self) -> Result<String> {
+        match self {
+            Response::LoginSuccess {
+                stream_session_id, ..
+            } => Ok(stream_session_id),
+            other => other.into_result().and(Err(ErrorKind::InvalidResponse)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct NoArguments {}
+
+/// Commands that take no arguments.
+pub type SimpleCommand = BaseCommand<NoArguments>;
+impl SimpleCommand {
+    fn named(command: &'static str) -> Self {
+        Self {
+            command,
+            arguments: NoArguments {},
+        }
+    }
+
+    pub fn ping() -> Self {
+        Self::named("ping")
+    }
+
+    pub fn get_all_symbols() -> Self {
+        Self::named("getAllSymbols")
+    }
+
+    pub fn get_calendar() -> Self {
+        Self::named("getCalendar")
+    }
+
+    pub fn get_margin_level() -> Self {
+        Self::named("getMarginLevel")
+    }
+
+    pub fn get_server_time() -> Self {
+        Self::named("getServerTime")
+    }
+
+    pub fn get_step_rules() -> Self {
+        Self::named("getStepRules")
+    }
+
+    pub fn get_current_user_data() -> Self {
+        Self::named("getCurrentUserData")
+    }
+
+    pub fn get_version() -> Self {
+        Self::named("getVersion")
+    }
+
+    pub fn logout() -> Self {
+        Self::named("logout")
+    }
+}
+
 #[derive(Serialize)]
-struct GetTradeRecordsArguments {
+pub struct GetSymbolArguments {
+    symbol: String,
+}
+
+pub type GetSymbolCommand = BaseCommand<GetSymbolArguments>;
+impl GetSymbolCommand {
+    pub fn new(symbol: String) -> Self {
+        Self {
+            command: "getSymbol",
+            arguments: GetSymbolArguments { symbol },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetTradesHistoryArguments {
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    start: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    end: DateTime<Utc>,
+}
+
+pub type GetTradesHistoryCommand = BaseCommand<GetTradesHistoryArguments>;
+impl GetTradesHistoryCommand {
+    pub fn new(start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        Self {
+            command: "getTradesHistory",
+            arguments: GetTradesHistoryArguments { start, end },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetNewsArguments {
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    start: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    end: DateTime<Utc>,
+}
+
+pub type GetNewsCommand = BaseCommand<GetNewsArguments>;
+impl GetNewsCommand {
+    pub fn new(start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        Self {
+            command: "getNews",
+            arguments: GetNewsArguments { start, end },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetIbsHistoryArguments {
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    start: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    end: DateTime<Utc>,
+}
+
+pub type GetIbsHistoryCommand = BaseCommand<GetIbsHistoryArguments>;
+impl GetIbsHistoryCommand {
+    pub fn new(start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        Self {
+            command: "getIbsHistory",
+            arguments: GetIbsHistoryArguments { start, end },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetTradingHoursArguments {
+    symbols: Vec<String>,
+}
+
+pub type GetTradingHoursCommand = BaseCommand<GetTradingHoursArguments>;
+impl GetTradingHoursCommand {
+    pub fn new(symbols: Vec<String>) -> Self {
+        Self {
+            command: "getTradingHours",
+            arguments: GetTradingHoursArguments { symbols },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetTradeRecordsArguments {
     orders: Vec<OrderId>,
 }
 
-type GetTradeRecordsCommand = BaseCommand<GetTradeRecordsArguments>;
+pub type GetTradeRecordsCommand = BaseCommand<GetTradeRecordsArguments>;
 impl GetTradeRecordsCommand {
-    fn new(orders: Vec<OrderId>) -> Self {
+    pub fn new(orders: Vec<OrderId>) -> Self {
         Self {
             command: "getTradeRecords",
             arguments: GetTradeRecordsArguments { orders },
@@ -59,14 +232,14 @@ impl GetTradeRecordsCommand {
 }
 
 #[derive(Serialize)]
-struct GetTradesArguments {
+pub struct GetTradesArguments {
     #[serde(rename = "openedOnly")]
     opened_only: bool,
 }
 
-type GetTradesCommand = BaseCommand<GetTradesArguments>;
+pub type GetTradesCommand = BaseCommand<GetTradesArguments>;
 impl GetTradesCommand {
-    pub(crate) fn new(opened_only: bool) -> Self {
+    pub fn new(opened_only: bool) -> Self {
         Self {
             command: "getTrades",
             arguments: GetTradesArguments { opened_only },
@@ -79,6 +252,7 @@ struct TradeTransInfo {
     cmd: OrderSide,
     #[serde(rename = "customComment")]
     custom_comment: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     expiration: Option<u64>,
     offset: usize,
     order: OrderId,
@@ -106,13 +280,13 @@ impl TradeTransactionCommand {
                 trade_trans_info: TradeTransInfo {
                     cmd: order.side.unwrap_or(OrderSide::Buy),
                     custom_comment: order.comment.unwrap_or_default().to_owned(),
-                    expiration: Some(9999999999999u64),
-                    offset: 0,
+                    expiration: order.expiration,
+                    offset: order.offset,
                     order: order.order,
-                    price: 1.0,
-                    sl: 0.0,
+                    price: order.price,
+                    sl: order.sl,
                     symbol: order.symbol.unwrap_or_default().to_owned(),
-                    tp: 0.0,
+                    tp: order.tp,
                     typ: order.kind.unwrap(),
                     volume: order.volume,
                 },
@@ -126,6 +300,142 @@ pub struct TradeTransactionResponse {
     pub order: OrderId,
 }
 
+#[derive(Serialize)]
+pub struct TradeTransactionStatusArguments {
+    order: OrderId,
+}
+
+pub type TradeTransactionStatusCommand = BaseCommand<TradeTransactionStatusArguments>;
+impl TradeTransactionStatusCommand {
+    pub fn new(order: OrderId) -> Self {
+        Self {
+            command: "tradeTransactionStatus",
+            arguments: TradeTransactionStatusArguments { order },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetCommissionDefArguments {
+    symbol: String,
+    volume: f64,
+}
+
+pub type GetCommissionDefCommand = BaseCommand<GetCommissionDefArguments>;
+impl GetCommissionDefCommand {
+    pub fn new(symbol: String, volume: f64) -> Self {
+        Self {
+            command: "getCommissionDef",
+            arguments: GetCommissionDefArguments { symbol, volume },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetMarginTradeArguments {
+    symbol: String,
+    volume: f64,
+}
+
+pub type GetMarginTradeCommand = BaseCommand<GetMarginTradeArguments>;
+impl GetMarginTradeCommand {
+    pub fn new(symbol: String, volume: f64) -> Self {
+        Self {
+            command: "getMarginTrade",
+            arguments: GetMarginTradeArguments { symbol, volume },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetProfitCalculationArguments {
+    #[serde(rename = "closePrice")]
+    close_price: f64,
+    cmd: OrderSide,
+    #[serde(rename = "openPrice")]
+    open_price: f64,
+    symbol: String,
+    volume: f64,
+}
+
+pub type GetProfitCalculationCommand = BaseCommand<GetProfitCalculationArguments>;
+impl GetProfitCalculationCommand {
+    pub fn new(
+        symbol: String,
+        side: OrderSide,
+        volume: f64,
+        open_price: f64,
+        close_price: f64,
+    ) -> Self {
+        Self {
+            command: "getProfitCalculation",
+            arguments: GetProfitCalculationArguments {
+                close_price,
+                cmd: side,
+                open_price,
+                symbol,
+                volume,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct VersionResponse {
+    pub version: String,
+}
+
+#[derive(Deserialize)]
+pub struct ProfitCalculationResponse {
+    pub profit: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ChartLastInfo {
+    pub period: Period,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub start: DateTime<Utc>,
+    pub symbol: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ChartRangeInfo {
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub end: DateTime<Utc>,
+    pub period: Period,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub start: DateTime<Utc>,
+    pub symbol: String,
+    /// If non-zero, `end` is ignored and this many candles are returned
+    /// after (positive) or before (negative) `start`.
+    pub ticks: i32,
+}
+
+#[derive(Serialize)]
+pub struct ChartArguments<I: Serialize> {
+    info: I,
+}
+
+pub type GetChartLastCommand = BaseCommand<ChartArguments<ChartLastInfo>>;
+impl GetChartLastCommand {
+    pub fn new(info: ChartLastInfo) -> Self {
+        Self {
+            command: "getChartLastRequest",
+            arguments: ChartArguments { info },
+        }
+    }
+}
+
+pub type GetChartRangeCommand = BaseCommand<ChartArguments<ChartRangeInfo>>;
+impl GetChartRangeCommand {
+    pub fn new(info: ChartRangeInfo) -> Self {
+        Self {
+            command: "getChartRangeRequest",
+            arguments: ChartArguments { info },
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct LoginArguments<'a> {
     #[serde(rename = "userId")]
@@ -137,13 +447,13 @@ pub struct LoginArguments<'a> {
 
 pub type LoginCommand<'a> = BaseCommand<LoginArguments<'a>>;
 impl<'a> LoginCommand<'a> {
-    pub fn new(user_id: usize, password: &'a str) -> Self {
+    pub fn new(user_id: usize, password: &'a str, app_name: &'a str) -> Self {
         Self {
             command: "login",
             arguments: LoginArguments {
                 user_id,
                 password,
-                app_name: "",
+                app_name,
             },
         }
     }
@@ -210,23 +520,63 @@ streaming_commands_with_fields! {
 
 #[derive(Deserialize, Debug)]
 pub struct StreamingProfitRecord {
-    order: OrderId,
-    order2: OrderId,
-    position: PositionId,
-    profit: f64,
+    pub order: OrderId,
+    pub order2: OrderId,
+    pub position: PositionId,
+    pub profit: f64,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct StreamingCandleRecord {
-    close: f64,
-    ctm: u64,
-    ctmString: String,
-    high: f64,
-    low: f64,
-    open: f64,
-    quoteId: u64,
-    symbol: String,
-    vol: f64,
+    pub close: f64,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub ctm: DateTime<Utc>,
+    pub high: f64,
+    pub low: f64,
+    pub open: f64,
+    #[serde(rename = "quoteId")]
+    pub quote_id: u64,
+    pub symbol: String,
+    pub vol: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamingTradeStatusRecord {
+    #[serde(rename = "customComment")]
+    pub custom_comment: Option<String>,
+    pub message: Option<String>,
+    pub order: OrderId,
+    pub price: f64,
+    #[serde(rename = "requestStatus")]
+    pub request_status: RequestStatus,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamingTickRecord {
+    pub ask: f64,
+    pub bid: f64,
+    #[serde(rename = "askVolume")]
+    pub ask_volume: Option<u64>,
+    #[serde(rename = "bidVolume")]
+    pub bid_volume: Option<u64>,
+    pub high: f64,
+    pub low: f64,
+    pub level: i32,
+    #[serde(rename = "quoteId")]
+    pub quote_id: u64,
+    #[serde(rename = "spreadRaw")]
+    pub spread_raw: f64,
+    #[serde(rename = "spreadTable")]
+    pub spread_table: f64,
+    pub symbol: String,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamingKeepAliveRecord {
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub timestamp: DateTime<Utc>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -240,9 +590,30 @@ pub enum StreamingMessage {
     Profit { data: StreamingProfitRecord },
     #[serde(rename = "candles")]
     Candles { data: StreamingCandleRecord },
+    #[serde(rename = "tradeStatus")]
+    TradeStatus { data: StreamingTradeStatusRecord },
+    #[serde(rename = "tickPrices")]
+    TickPrices { data: StreamingTickRecord },
+    #[serde(rename = "keepAlive")]
+    KeepAlive { data: StreamingKeepAliveRecord },
+    #[serde(rename = "news")]
+    News { data: NewsRecord },
+    /// Generated locally when no keep-alive arrived on the streaming connection for too long.
+    #[serde(skip)]
+    ConnectionStale { since: std::time::Duration },
+}
+
+/// Optional parameters of a tick price subscription.
+#[derive(Default, Clone, Debug)]
+pub struct TickOptions {
+    /// Minimal interval between two updates, in milliseconds.
+    pub min_arrival_time: Option<u64>,
+    /// Deepest market depth level to receive, `0` being the best price.
+    pub max_level: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "command")]
 pub enum StreamingCommandType {
     #[serde(rename = "getBalance")]
     GetBalance,
@@ -250,14 +621,45 @@ pub enum StreamingCommandType {
     StopBalance,
     #[serde(rename = "getTrades")]
     GetTrades,
+    #[serde(rename = "stopTrades")]
+    StopTrades,
     #[serde(rename = "getCandles")]
     GetCandles { symbol: String },
+    #[serde(rename = "stopCandles")]
+    StopCandles { symbol: String },
     #[serde(rename = "getProfits")]
     GetProfits,
+    #[serde(rename = "stopProfits")]
+    StopProfits,
+    #[serde(rename = "getTradeStatus")]
+    GetTradeStatus,
+    #[serde(rename = "stopTradeStatus")]
+    StopTradeStatus,
+    #[serde(rename = "getNews")]
+    GetNews,
+    #[serde(rename = "stopNews")]
+    StopNews,
+    #[serde(rename = "getKeepAlive")]
+    GetKeepAlive,
+    #[serde(rename = "ping")]
+    Ping,
+    #[serde(rename = "stopKeepAlive")]
+    StopKeepAlive,
+    #[serde(rename = "getTickPrices")]
+    GetTickPrices {
+        symbol: String,
+        #[serde(rename = "minArrivalTime", skip_serializing_if = "Option::is_none")]
+        min_arrival_time: Option<u64>,
+        #[serde(rename = "maxLevel", skip_serializing_if = "Option::is_none")]
+        max_level: Option<u32>,
+    },
+    #[serde(rename = "stopTickPrices")]
+    StopTickPrices { symbol: String },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StreamingCommand {
+    #[serde(flatten)]
     pub command: StreamingCommandType,
     #[serde(rename = "streamSessionId")]
     pub stream_session_id: String,
@@ -293,4 +695,27 @@ impl StreamingCommand {
     pub fn stop_balance(stream_session_id: String) -> Self {
         Self::new(StreamingCommandType::StopBalance, stream_session_id)
     }
+
+    pub fn get_tick_prices(
+        stream_session_id: String,
+        symbol: String,
+        options: TickOptions,
+    ) -> Self {
+        Self::new(
+            StreamingCommandType::GetTickPrices {
+                symbol,
+                min_arrival_time: options.min_arrival_time,
+                max_level: options.max_level,
+            },
+            stream_session_id,
+        )
+    }
+
+    pub fn get_trade_status(stream_session_id: String) -> Self {
+        Self::new(StreamingCommandType::GetTradeStatus, stream_session_id)
+    }
+
+    pub fn stop_trade_status(stream_session_id: String) -> Self {
+        Self::new(StreamingCommandType::StopTradeStatus, stream_session_id)
+    }
 }
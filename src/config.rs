@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use derive_builder::Builder;
+
+use crate::json_socket::JsonSocket;
+use crate::rate_limit::RateLimit;
+use crate::transport::TlsTransport;
+use crate::websocket::WebSocketTransport;
+use crate::{Result, XTB};
+
+const DEFAULT_XAPI_ADDRESS: &str = "xapi.xtb.com";
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Environment {
+    #[default]
+    Demo,
+    Real,
+}
+
+impl Environment {
+    pub fn request_port(self) -> u16 {
+        match self {
+            Environment::Demo => 5124,
+            Environment::Real => 5112,
+        }
+    }
+
+    pub fn streaming_port(self) -> u16 {
+        match self {
+            Environment::Demo => 5125,
+            Environment::Real => 5113,
+        }
+    }
+
+    pub fn request_url(self) -> &'static str {
+        match self {
+            Environment::Demo => "wss://ws.xtb.com/demo",
+            Environment::Real => "wss://ws.xtb.com/real",
+        }
+    }
+
+    pub fn streaming_url(self) -> &'static str {
+        match self {
+            Environment::Demo => "wss://ws.xtb.com/demoStream",
+            Environment::Real => "wss://ws.xtb.com/realStream",
+        }
+    }
+}
+
+/// How messages are carried to the server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TransportKind {
+    /// JSON over a raw TLS connection, using `host` and the ports.
+    #[default]
+    Tls,
+    /// JSON over WebSocket, using the URLs.
+    WebSocket,
+}
+
+/// Where and how to connect. Ports and URLs default to the ones of `environment`.
+#[derive(Builder, Debug, Clone)]
+#[builder(name = "XTBBuilder")]
+pub struct XtbConfig {
+    #[builder(default)]
+    pub environment: Environment,
+    #[builder(setter(into), default = "DEFAULT_XAPI_ADDRESS.to_owned()")]
+    pub host: String,
+    #[builder(setter(strip_option), default)]
+    pub request_port: Option<u16>,
+    #[builder(setter(strip_option), default)]
+    pub streaming_port: Option<u16>,
+    #[builder(default)]
+    pub transport: TransportKind,
+    #[builder(setter(into, strip_option), default)]
+    pub request_url: Option<String>,
+    #[builder(setter(into, strip_option), default)]
+    pub streaming_url: Option<String>,
+    #[builder(setter(into), default)]
+    pub app_name: String,
+    /// Timeout for establishing each connection, `None` waits for the OS.
+    #[builder(setter(strip_option), default)]
+    pub connect_timeout: Option<Duration>,
+    /// How long to wait for the response to a request, `None` waits forever.
+    #[builder(setter(strip_option), default)]
+    pub request_timeout: Option<Duration>,
+    /// How often both connections are pinged to keep them alive.
+    #[builder(default = "DEFAULT_HEARTBEAT_INTERVAL")]
+    pub heartbeat_interval: Duration,
+    /// Applies to each connection separately, and to orders on top of that.
+    #[builder(default)]
+    pub rate_limit: RateLimit,
+    /// Orders wait for their turn when their rate limit is hit. When disabled
+    /// they fail with `ErrorKind::RateLimited` instead of going out late.
+    /// Other requests, including the ones sent before an order, always wait.
+    #[builder(default = "true")]
+    pub queue_orders: bool,
+}
+
+impl XtbConfig {
+    pub fn demo() -> Self {
+        Self::for_environment(Environment::Demo)
+    }
+
+    pub fn real() -> Self {
+        Self::for_environment(Environment::Real)
+    }
+
+    fn for_environment(environment: Environment) -> Self {
+        XTBBuilder::default()
+            .environment(environment)
+            .build()
+            .unwrap()
+    }
+
+    pub fn request_port(&self) -> u16 {
+        self.request_port
+            .unwrap_or_else(|| self.environment.request_port())
+    }
+
+    pub fn streaming_port(&self) -> u16 {
+        self.streaming_port
+            .unwrap_or_else(|| self.environment.streaming_port())
+    }
+
+    pub fn request_url(&self) -> &str {
+        self.request_url
+            .as_deref()
+            .unwrap_or_else(|| self.environment.request_url())
+    }
+
+    pub fn streaming_url(&self) -> &str {
+        self.streaming_url
+            .as_deref()
+            .unwrap_or_else(|| self.environment.streaming_url())
+    }
+
+    pub(crate) fn connect_request(&self) -> Result<JsonSocket> {
+        let mut socket = match self.transport {
+            TransportKind::Tls => JsonSocket::new(TlsTransport::connect(
+                &self.host,
+                self.request_port(),
+                self.connect_timeout,
+            )?),
+            TransportKind::WebSocket => JsonSocket::new(WebSocketTransport::connect(
+                self.request_url(),
+                self.connect_timeout,
+            )?),
+        };
+        socket.set_read_timeout(self.request_timeout)?;
+        socket.set_rate_limit(self.rate_limit);
+        Ok(socket)
+    }
+
+    pub(crate) fn connect_streaming(&self) -> Result<JsonSocket> {
+        let mut socket = match self.transport {
+            TransportKind::Tls => JsonSocket::new(TlsTransport::connect(
+                &self.host,
+                self.streaming_port(),
+                self.connect_timeout,
+            )?),
+            TransportKind::WebSocket => JsonSocket::new(WebSocketTransport::connect(
+                self.streaming_url(),
+                self.connect_timeout,
+            )?),
+        };
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    }
+}
+
+impl XTBBuilder {
+    pub fn connect(&self, user_id: usize, password: &str) -> Result<XTB> {
+        XTB::connect_with(self.build()?, user_id, password)
+    }
+}
+
+impl From<XTBBuilderError> for crate::ErrorKind {
+    fn from(value: XTBBuilderError) -> Self {
+        crate::ErrorKind::InvalidConfig(value.to_string())
+    }
+}
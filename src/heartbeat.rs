@@ -0,0 +1,58 @@
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::calls;
+use crate::commands::*;
+use crate::json_socket::JsonSocket;
+use crate::subscription::StreamingConnection;
+
+/// Pings both connections every `interval` so the server doesn't drop them as idle.
+pub(crate) struct Heartbeat {
+    interval: flume::Sender<Duration>,
+    thread: JoinHandle<()>,
+}
+
+impl Heartbeat {
+    pub(crate) fn spawn(
+        request: Arc<Mutex<JsonSocket>>,
+        streaming: Arc<StreamingConnection>,
+        mut interval: Duration,
+    ) -> Self {
+        let (sender, receiver) = flume::unbounded();
+
+        let thread = std::thread::spawn(move || loop {
+            match receiver.recv_timeout(interval) {
+                Ok(new_interval) => {
+                    interval = new_interval;
+                    continue;
+                }
+                Err(flume::RecvTimeoutError::Disconnected) => return,
+                Err(flume::RecvTimeoutError::Timeout) => {}
+            }
+
+            if let Err(e) = request.lock().unwrap().call(calls::ping()) {
+                log::warn!("Request connection ping failed: {:?}", e);
+            }
+
+            if let Err(e) = streaming.send(StreamingCommandType::Ping) {
+                log::warn!("Streaming connection ping failed: {:?}", e);
+            }
+        });
+
+        Self {
+            interval: sender,
+            thread,
+        }
+    }
+
+    /// Reschedules the pings, starting from now.
+    pub(crate) fn set_interval(&self, interval: Duration) {
+        let _ = self.interval.send(interval);
+    }
+
+    pub(crate) fn stop(self) {
+        drop(self.interval);
+        let _ = self.thread.join();
+    }
+}
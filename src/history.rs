@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::types::*;
+use crate::Result;
+
+const DEFAULT_WINDOW_DAYS: i64 = 7;
+
+/// Splits a time range into windows and drops trades an earlier window already returned.
+pub(crate) struct Windows {
+    next_start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    window: Duration,
+    seen: HashSet<PositionId>,
+}
+
+impl Windows {
+    pub(crate) fn new(range: Range<DateTime<Utc>>) -> Self {
+        Self {
+            next_start: range.start,
+            end: range.end,
+            window: Duration::days(DEFAULT_WINDOW_DAYS),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// # Panics
+    ///
+    /// If `window` isn't positive, the range would never be covered.
+    pub(crate) fn set_window(&mut self, window: Duration) {
+        assert!(
+            window > Duration::zero(),
+            "history window must be positive, got {}",
+            window
+        );
+        self.window = window;
+    }
+
+    /// The next span to request, `None` once the whole range is covered.
+    pub(crate) fn next_window(&mut self) -> Option<Range<DateTime<Utc>>> {
+        if self.next_start >= self.end {
+            return None;
+        }
+        let start = self.next_start;
+        self.next_start = (start + self.window).min(self.end);
+        Some(start..self.next_start)
+    }
+
+    /// Trades on the border of two windows are returned by both requests.
+    pub(crate) fn is_new(&mut self, record: &TradeRecord) -> bool {
+        self.seen.insert(record.position)
+    }
+}
+
+/// Fetches the trades closed in a time span, one request per window.
+pub trait FetchTrades {
+    fn fetch(&mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<TradeRecord>>;
+}
+
+impl<F> FetchTrades for F
+where
+    F: FnMut(DateTime<Utc>, DateTime<Utc>) -> Result<Vec<TradeRecord>>,
+{
+    fn fetch(&mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<TradeRecord>> {
+        self(start, end)
+    }
+}
+
+/// Closed trades in a time range, fetched one window at a time.
+///
+/// Trades on the border of two windows are returned by both requests, so
+/// every position is only yielded once.
+pub struct TradeHistory<F> {
+    fetch: F,
+    windows: Windows,
+    buffer: std::vec::IntoIter<TradeRecord>,
+    failed: bool,
+}
+
+impl<F: FetchTrades> TradeHistory<F> {
+    pub(crate) fn new(range: Range<DateTime<Utc>>, fetch: F) -> Self {
+        Self {
+            fetch,
+            windows: Windows::new(range),
+            buffer: Vec::new().into_iter(),
+            failed: false,
+        }
+    }
+
+    /// Length of the time span requested at once, a week by default.
+    ///
+    /// # Panics
+    ///
+    /// If `window` is zero or negative.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.windows.set_window(window);
+        self
+    }
+}
+
+impl<F: FetchTrades> Iterator for TradeHistory<F> {
+    type Item = Result<TradeRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.buffer.next() {
+                if self.windows.is_new(&record) {
+                    return Some(Ok(record));
+                }
+                continue;
+            }
+
+            if self.failed {
+                return None;
+            }
+            let window = self.windows.next_window()?;
+            match self.fetch.fetch(window.start, window.end) {
+                Ok(records) => self.buffer = records.into_iter(),
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+    use chrono::TimeZone;
+
+    fn trade(position: usize) -> TradeRecord {
+        TradeRecord {
+            position: PositionId(position),
+            ..Default::default()
+        }
+    }
+
+    fn day(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn yields_border_trades_once() {
+        let mut requests = Vec::new();
+        let positions: Vec<_> = TradeHistory::new(day(1)..day(15), |start, end| {
+            requests.push(start..end);
+            // The trade closed on the border shows up in both windows
+            Ok(match requests.len() {
+                1 => vec![trade(1), trade(2)],
+                _ => vec![trade(2), trade(3)],
+            })
+        })
+        .map(|record| record.unwrap().position.0)
+        .collect();
+
+        assert_eq!(positions, [1, 2, 3]);
+        assert_eq!(requests, [day(1)..day(8), day(8)..day(15)]);
+    }
+
+    #[test]
+    fn last_window_stops_at_the_end() {
+        let mut requests = Vec::new();
+        let count = TradeHistory::new(day(1)..day(11), |start, end| {
+            requests.push(start..end);
+            Ok(Vec::new())
+        })
+        .window(Duration::days(4))
+        .count();
+
+        assert_eq!(count, 0);
+        assert_eq!(requests, [day(1)..day(5), day(5)..day(9), day(9)..day(11)]);
+    }
+
+    #[test]
+    fn stops_after_an_error() {
+        let mut calls = 0;
+        let mut history = TradeHistory::new(day(1)..day(29), |_, _| {
+            calls += 1;
+            Err(ErrorKind::InvalidResponse)
+        });
+
+        assert!(matches!(
+            history.next(),
+            Some(Err(ErrorKind::InvalidResponse))
+        ));
+        assert!(history.next().is_none());
+        drop(history);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "history window must be positive")]
+    fn rejects_an_empty_window() {
+        let _ = TradeHistory::new(day(1)..day(2), |_, _| Ok(Vec::new())).window(Duration::zero());
+    }
+}
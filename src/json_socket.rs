@@ -1,73 +1,75 @@
-use once_cell::sync::Lazy;
-use rustls::{ClientConfig, ClientSession, Session, StreamOwned};
 use serde::de::DeserializeOwned;
-use serde::{Deserialize, Serialize};
-use std::io::prelude::*;
-use std::io::BufRead;
-use std::io::BufReader;
-use std::sync::Arc;
+use serde::Serialize;
+use std::time::Duration;
 
+use crate::calls::Call;
+use crate::commands::Response;
+use crate::rate_limit::{RateLimit, RateLimiter};
+use crate::transport::Transport;
 use crate::ErrorKind;
 use crate::Result;
 
-static RUSTLS_CLIENT_CONFIG: Lazy<Arc<ClientConfig>> = Lazy::new(|| {
-    let mut config = ClientConfig::new();
-    let roots: &webpki::TLSServerTrustAnchors = &webpki_roots::TLS_SERVER_ROOTS;
-    config.root_store.add_server_trust_anchors(roots);
-    std::sync::Arc::new(config)
-});
-
 pub struct JsonSocket {
-    host: String,
-    stream: BufReader<StreamOwned<ClientSession, std::net::TcpStream>>,
+    transport: Box<dyn Transport>,
+    limiter: Option<RateLimiter>,
+    /// Set after a read timed out. The late reply would be taken for the
+    /// answer to the next request, so the connection can't be used anymore.
+    poisoned: bool,
 }
 
 impl JsonSocket {
-    pub fn connect(host: &str, port: usize) -> Result<Self> {
-        let dns_name = webpki::DNSNameRef::try_from_ascii_str(host).unwrap();
-        let client = ClientSession::new(&RUSTLS_CLIENT_CONFIG, dns_name);
-        let socket = std::net::TcpStream::connect(format!("{}:{}", host, port))
-            .map_err(|e| ErrorKind::Disconnected(e))?;
-        let stream = rustls::StreamOwned::new(client, socket);
-
-        Ok(Self {
-            host: host.to_string(),
-            stream: BufReader::new(stream),
-        })
+    pub fn new<T: Transport + 'static>(transport: T) -> Self {
+        Self {
+            transport: Box::new(transport),
+            limiter: None,
+            poisoned: false,
+        }
+    }
+
+    pub fn set_rate_limit(&mut self, limit: RateLimit) {
+        self.limiter = Some(RateLimiter::new(limit));
     }
 
-    pub fn close(mut self) {
-        self.stream
-            .get_mut()
-            .sock
-            .shutdown(std::net::Shutdown::Both);
+    /// Makes receiving fail with `ErrorKind::Timeout` when nothing arrives in time.
+    /// After that, every call fails with `ErrorKind::Disconnected`.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.transport.set_read_timeout(timeout)
     }
 
-    fn receive_one_message(&mut self) -> Result<String> {
-        let mut buffer = String::new();
-        match self.stream.read_line(&mut buffer) {
-            Ok(_size) => {
-                // Discard the second newline
-                std::io::copy(&mut self.stream.by_ref().take(1), &mut std::io::sink());
-                return Ok(buffer);
-            }
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                return Err(ErrorKind::WouldBlock)
-            }
-            Err(e) => return Err(ErrorKind::Disconnected(e)),
+    pub fn close(&mut self) -> Result<()> {
+        if self.poisoned {
+            // Already closed
+            return Ok(());
         }
+        self.transport.close()
+    }
+
+    fn check_poisoned(&self) -> Result<()> {
+        if self.poisoned {
+            return Err(ErrorKind::Disconnected(
+                std::io::ErrorKind::NotConnected.into(),
+            ));
+        }
+        Ok(())
     }
 
     pub fn send_string(&mut self, msg: &str) -> Result<()> {
         //println!("{}", msg);
-        self.stream
-            .get_mut()
-            .write_all(msg.as_bytes())
-            .map_err(|e| ErrorKind::Disconnected(e))
+        self.check_poisoned()?;
+        if let Some(limiter) = &mut self.limiter {
+            limiter.acquire();
+        }
+        self.transport.send_message(msg)
     }
 
     pub fn recv_string(&mut self) -> Result<String> {
-        self.receive_one_message()
+        self.check_poisoned()?;
+        let message = self.transport.recv_message();
+        if let Err(ErrorKind::Timeout) = message {
+            self.poisoned = true;
+            let _ = self.transport.close();
+        }
+        message
     }
 
     pub fn send_recv_string(&mut self, message: &str) -> Result<String> {
@@ -76,14 +78,14 @@ impl JsonSocket {
     }
 
     pub fn recv<D: DeserializeOwned>(&mut self) -> Result<D> {
-        let message = self.receive_one_message()?;
+        let message = self.recv_string()?;
         //println!("{}", message);
 
         serde_json::from_str(&message).map_err(ErrorKind::JsonError)
     }
 
     pub fn send<S: Serialize>(&mut self, message: &S) -> Result<()> {
-        let msg = serde_json::to_string(message).map_err(|e| ErrorKind::JsonError(e))?;
+        let msg = serde_json::to_string(message).map_err(ErrorKind::JsonError)?;
         //println!("{}", msg);
         self.send_string(&msg)
     }
@@ -93,11 +95,15 @@ impl JsonSocket {
         self.recv()
     }
 
+    pub(crate) fn call<C: Serialize, D: DeserializeOwned, T>(
+        &mut self,
+        call: Call<C, D, T>,
+    ) -> Result<T> {
+        let response: Response<D> = self.send_recv(&call.command)?;
+        call.decode(response)
+    }
+
     pub fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
-        self.stream
-            .get_mut()
-            .sock
-            .set_nonblocking(nonblocking)
-            .map_err(|e| ErrorKind::Disconnected(e))
+        self.transport.set_nonblocking(nonblocking)
     }
 }
@@ -1,20 +1,52 @@
+#[cfg(feature = "async")]
+mod async_client;
+mod calendar;
+mod calls;
+mod clock;
 mod commands;
+mod config;
+mod heartbeat;
+mod history;
 mod json_socket;
+mod rate_limit;
+mod step;
+mod subscription;
+mod trading_hours;
 mod transaction;
+mod transport;
 mod types;
+mod websocket;
 
 use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "async")]
+pub use async_client::AsyncXTB;
+pub use calendar::*;
+use chrono::{DateTime, Utc};
+pub use clock::*;
+pub use config::*;
 use flume::select::Selector;
+pub use history::{FetchTrades, TradeHistory};
+pub use rate_limit::RateLimit;
+pub use step::*;
+pub use trading_hours::*;
 pub use transaction::*;
+pub use transport::{TlsTransport, Transport};
 pub use types::*;
 
 pub use commands::*;
+use heartbeat::Heartbeat;
 use json_socket::JsonSocket;
+use rate_limit::RateLimiter;
+use subscription::StreamingConnection;
+pub use subscription::{StreamKind, Subscription};
+pub use websocket::WebSocketTransport;
 
-const DEFAULT_XAPI_ADDRESS: &str = "xapi.xtb.com";
-const DEFAULT_XAPI_DEMO_PORT: usize = 5124;
-const DEFAULT_XAPI_DEMO_STREAMING_PORT: usize = 5125;
+const TRANSACTION_STATUS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// The server sends a keep-alive every few seconds, this leaves plenty of slack.
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(15);
 
 #[derive(Debug)]
 pub enum ErrorKind {
@@ -24,93 +56,228 @@ pub enum ErrorKind {
     ApiError(String, String),
     InternalError,
     WouldBlock,
+    Timeout,
+    InvalidTransaction(String),
+    InvalidConfig(String),
+    /// The symbol can't be traded right now, with the time its market opens next.
+    MarketClosed(String, Option<DateTime<Utc>>),
+    /// Sending now would exceed the rate limit, with the time until it wouldn't.
+    RateLimited(Duration),
+    /// The order was sent, but still wasn't accepted or rejected in time.
+    PendingTimeout(TransactionStatus),
 }
 
 pub type Result<T> = std::result::Result<T, ErrorKind>;
 
 pub struct XTB {
-    req_socket: JsonSocket,
-    streaming_id: String,
+    config: XtbConfig,
+    req_socket: Arc<Mutex<JsonSocket>>,
     stream: flume::Receiver<StreamingMessage>,
-    stream_socket: Arc<Mutex<JsonSocket>>,
+    streaming: Arc<StreamingConnection>,
+    reader: Option<JoinHandle<()>>,
+    heartbeat: Option<Heartbeat>,
+    _keep_alive: Subscription,
+    logged_out: bool,
+    /// Spaces out orders on top of the connection's own limit, so requests
+    /// made by the client itself don't use up the budget for orders.
+    orders: RateLimiter,
+    clock: Option<ServerClock>,
+    step_rules: Option<step::StepRules>,
+    market_hours: Option<TradingCalendar>,
+    account: UserDataRecord,
+    api_version: String,
 }
 
 impl XTB {
+    /// Connects to the demo servers with the default settings.
     pub fn connect(user_id: usize, password: &str) -> Result<Self> {
-        let mut request_socket = JsonSocket::connect(DEFAULT_XAPI_ADDRESS, DEFAULT_XAPI_DEMO_PORT)?;
-
-        let cmd = LoginCommand::new(user_id, password);
-        let response: Response<()> = request_socket.send_recv(&cmd)?;
-
-        use commands::Response::*;
-        let streaming_id = match response {
-            LoginSuccess {
-                stream_session_id, ..
-            } => stream_session_id,
-            Success { .. } => return Err(ErrorKind::InvalidResponse),
-            Response::Error {
-                error_code,
-                error_desc,
-                ..
-            } => return Err(ErrorKind::ApiError(error_code, error_desc)),
-        };
+        Self::connect_with(XtbConfig::demo(), user_id, password)
+    }
 
-        let streaming_socket = Arc::new(Mutex::new(JsonSocket::connect(
-            DEFAULT_XAPI_ADDRESS,
-            DEFAULT_XAPI_DEMO_STREAMING_PORT,
-        )?));
+    pub fn connect_with(config: XtbConfig, user_id: usize, password: &str) -> Result<Self> {
+        let mut request_socket = config.connect_request()?;
 
-        {
-            streaming_socket.lock().unwrap().set_nonblocking(true);
-        }
+        let streaming_id =
+            request_socket.call(calls::login(user_id, password, &config.app_name))?;
+        let account = request_socket.call(calls::current_user_data())?;
+        let api_version = request_socket.call(calls::version())?;
+
+        let streaming_socket = config.connect_streaming()?;
+        let streaming = Arc::new(StreamingConnection::new(
+            streaming_socket,
+            streaming_id,
+            config.rate_limit,
+        ));
 
         let (stream_sender, stream_receiver) = flume::unbounded();
 
-        let stream_socket_clone = streaming_socket.clone();
-        std::thread::spawn(move || loop {
-            {
-                let mut sock = stream_socket_clone.lock().unwrap();
-                match sock.recv() {
-                    Ok(msg) => {
-                        let msg: StreamingMessage = msg;
-                        stream_sender.send(msg).unwrap();
+        let streaming_clone = streaming.clone();
+        let mut last_keep_alive = Instant::now();
+        let mut stale = false;
+        let reader = std::thread::spawn(move || loop {
+            if streaming_clone.is_closed() {
+                return;
+            }
+            let mut sock = streaming_clone.socket.lock().unwrap();
+            match sock.recv() {
+                Ok(msg) => {
+                    drop(sock);
+                    let msg: StreamingMessage = msg;
+                    if let StreamingMessage::KeepAlive { .. } = msg {
+                        last_keep_alive = Instant::now();
+                        stale = false;
                     }
-                    Err(ErrorKind::WouldBlock) => {
-                        drop(sock);
-                        std::thread::sleep(std::time::Duration::from_millis(100));
+                    // Messages can still arrive for a short while after a stream was stopped
+                    let active = match msg.kind() {
+                        Some(kind) => streaming_clone.is_active(&kind),
+                        None => true,
+                    };
+                    if active {
+                        stream_sender.send(msg).unwrap();
                     }
-                    Err(e) => {
-                        println!("Error: {:?}", e);
-                        return;
+                }
+                Err(ErrorKind::WouldBlock) => {
+                    drop(sock);
+                    if !stale
+                        && last_keep_alive.elapsed() > KEEP_ALIVE_TIMEOUT
+                        && streaming_clone.is_active(&StreamKind::KeepAlive)
+                    {
+                        stale = true;
+                        let since = last_keep_alive.elapsed();
+                        stream_sender
+                            .send(StreamingMessage::ConnectionStale { since })
+                            .unwrap();
                     }
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+                Err(ErrorKind::JsonError(e)) => {
+                    drop(sock);
+                    log::warn!("Skipping streaming message: {:?}", e);
+                }
+                // Returning drops the sender, which ends `handle_messages_blocking`
+                Err(e) => {
+                    log::warn!("Streaming connection closed: {:?}", e);
+                    return;
                 }
             }
         });
 
+        let keep_alive =
+            match streaming.subscribe(StreamKind::KeepAlive, StreamingCommandType::GetKeepAlive) {
+                Ok(keep_alive) => keep_alive,
+                Err(e) => {
+                    streaming.close();
+                    let _ = reader.join();
+                    let _ = streaming.socket.lock().unwrap().close();
+                    let _ = request_socket.close();
+                    return Err(e);
+                }
+            };
+        let request_socket = Arc::new(Mutex::new(request_socket));
+        let heartbeat = Heartbeat::spawn(
+            request_socket.clone(),
+            streaming.clone(),
+            config.heartbeat_interval,
+        );
+
+        let orders = RateLimiter::new(config.rate_limit);
         let s = Self {
+            config,
             req_socket: request_socket,
-            streaming_id,
-            stream_socket: streaming_socket,
+            streaming,
             stream: stream_receiver,
+            reader: Some(reader),
+            heartbeat: Some(heartbeat),
+            _keep_alive: keep_alive,
+            logged_out: false,
+            orders,
+            clock: None,
+            step_rules: None,
+            market_hours: None,
+            account,
+            api_version,
         };
 
         Ok(s)
     }
 
-    pub fn start_balance(&mut self) -> Result<()> {
-        let cmd = StreamingCommand::get_balance(self.streaming_id.clone());
-        println!("Locking and sending balance");
-        self.stream_socket.lock().unwrap().send(&cmd)?;
-        println!("Unlocked and sent balance");
-        Ok(())
+    pub fn start_balance(&mut self) -> Result<Subscription> {
+        self.streaming
+            .subscribe(StreamKind::Balance, StreamingCommandType::GetBalance)
     }
 
-    pub fn start_trades(&mut self) -> Result<()> {
-        let cmd = StreamingCommand::get_trades(self.streaming_id.clone());
-        println!("Locking and sending trades");
-        self.stream_socket.lock().unwrap().send(&cmd)?;
-        println!("Unlocked and sent trades");
-        Ok(())
+    pub fn start_trades(&mut self) -> Result<Subscription> {
+        self.streaming
+            .subscribe(StreamKind::Trades, StreamingCommandType::GetTrades)
+    }
+
+    pub fn start_trade_status(&mut self) -> Result<Subscription> {
+        self.streaming.subscribe(
+            StreamKind::TradeStatus,
+            StreamingCommandType::GetTradeStatus,
+        )
+    }
+
+    pub fn subscribe_profits(&mut self) -> Result<Subscription> {
+        self.streaming
+            .subscribe(StreamKind::Profits, StreamingCommandType::GetProfits)
+    }
+
+    pub fn subscribe_candles<S: Into<Symbol>>(&mut self, symbol: S) -> Result<Subscription> {
+        let symbol = symbol.into();
+        self.streaming.subscribe(
+            StreamKind::Candles(symbol.0.clone()),
+            StreamingCommandType::GetCandles { symbol: symbol.0 },
+        )
+    }
+
+    pub fn subscribe_news(&mut self) -> Result<Subscription> {
+        self.streaming
+            .subscribe(StreamKind::News, StreamingCommandType::GetNews)
+    }
+
+    pub fn subscribe_ticks<S: Into<Symbol>>(
+        &mut self,
+        symbol: S,
+        options: TickOptions,
+    ) -> Result<Subscription> {
+        let symbol = symbol.into();
+        self.streaming.subscribe(
+            StreamKind::TickPrices(symbol.0.clone()),
+            StreamingCommandType::GetTickPrices {
+                symbol: symbol.0,
+                min_arrival_time: options.min_arrival_time,
+                max_level: options.max_level,
+            },
+        )
+    }
+
+    pub fn config(&self) -> &XtbConfig {
+        &self.config
+    }
+
+    pub fn environment(&self) -> Environment {
+        self.config.environment
+    }
+
+    /// Account details, as fetched right after logging in.
+    pub fn account_info(&self) -> &UserDataRecord {
+        &self.account
+    }
+
+    pub fn api_version(&self) -> &str {
+        &self.api_version
+    }
+
+    /// Fetches the account details again, updating the cached ones.
+    pub fn refresh_account_info(&mut self) -> Result<&UserDataRecord> {
+        self.account = self.call(calls::current_user_data())?;
+        Ok(&self.account)
+    }
+
+    /// Streams that currently have at least one live `Subscription`.
+    pub fn active_subscriptions(&self) -> Vec<StreamKind> {
+        self.streaming.active()
     }
 
     pub fn messages(&mut self) -> impl Iterator<Item = StreamingMessage> + '_ {
@@ -124,6 +291,7 @@ impl XTB {
         Ok(())
     }
 
+    /// Returns once the streaming connection is closed.
     pub fn handle_messages_blocking<F: FnMut(StreamingMessage)>(
         &mut self,
         mut cb: F,
@@ -134,30 +302,334 @@ impl XTB {
         Ok(())
     }
 
+    fn call<C: serde::Serialize, D: serde::de::DeserializeOwned, T>(
+        &mut self,
+        call: calls::Call<C, D, T>,
+    ) -> Result<T> {
+        self.req_socket.lock().unwrap().call(call)
+    }
+
+    pub fn ping(&mut self) -> Result<()> {
+        self.call(calls::ping())
+    }
+
+    /// Changes how often both connections are pinged in the background.
+    pub fn set_heartbeat_interval(&mut self, interval: Duration) {
+        if let Some(heartbeat) = &self.heartbeat {
+            heartbeat.set_interval(interval);
+        }
+    }
+
+    /// Ends the session. The server closes both connections afterwards,
+    /// so this is usually followed by dropping the client.
+    pub fn logout(&mut self) -> Result<()> {
+        if self.logged_out {
+            return Ok(());
+        }
+        // The session is over even if the reply says otherwise
+        let call = calls::logout();
+        let response: Response<()> = self.req_socket.lock().unwrap().send_recv(&call.command)?;
+        self.logged_out = true;
+        call.decode(response)
+    }
+
+    /// Stops all streams, logs out and closes both connections.
+    ///
+    /// Dropping the client does the same, but ignores errors.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.tear_down()
+    }
+
+    fn tear_down(&mut self) -> Result<()> {
+        let reader = match self.reader.take() {
+            Some(reader) => reader,
+            None => return Ok(()),
+        };
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.stop();
+        }
+
+        // Keep going on errors, everything should get torn down anyway
+        let stopped = self.streaming.stop_all();
+        let logged_out = self.logout();
+
+        self.streaming.close();
+        let _ = reader.join();
+
+        let closed_streaming = self.streaming.socket.lock().unwrap().close();
+        let closed_request = self.req_socket.lock().unwrap().close();
+
+        stopped
+            .and(logged_out)
+            .and(closed_streaming)
+            .and(closed_request)
+    }
+
     pub fn send_transaction(&mut self, transaction: Transaction) -> Result<OrderId> {
-        let cmd = TradeTransactionCommand::new(transaction);
-        let response: Response<TradeTransactionResponse> = self.req_socket.send_recv(&cmd)?;
-        match response {
-            Response::Success { return_data, .. } => Ok(return_data.order),
-            Response::Error {
-                error_code,
-                error_desc,
-                ..
-            } => Err(ErrorKind::ApiError(error_code, error_desc)),
-            _ => Err(ErrorKind::InvalidResponse),
+        if self.config.queue_orders {
+            self.orders.acquire();
+        } else {
+            self.orders.try_acquire().map_err(ErrorKind::RateLimited)?;
+        }
+        self.call(calls::send_transaction(transaction))
+    }
+
+    pub fn transaction_status(&mut self, order: OrderId) -> Result<TransactionStatus> {
+        self.call(calls::transaction_status(order))
+    }
+
+    /// Sends a transaction and polls its status until the server accepts or rejects it.
+    ///
+    /// Fails with `ErrorKind::PendingTimeout`, holding the last status and with it
+    /// the order id, if the order is still pending when `timeout` runs out.
+    pub fn send_transaction_and_wait(
+        &mut self,
+        transaction: Transaction,
+        timeout: Duration,
+    ) -> Result<TransactionStatus> {
+        let deadline = Instant::now() + timeout;
+        let order = self.send_transaction(transaction)?;
+        loop {
+            let status = self.transaction_status(order)?;
+            if status.request_status.is_final() {
+                return Ok(status);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(ErrorKind::PendingTimeout(status));
+            }
+            std::thread::sleep(left.min(TRANSACTION_STATUS_POLL_INTERVAL));
+        }
+    }
+
+    /// Currently open positions.
+    pub fn open_trades(&mut self) -> Result<Vec<TradeRecord>> {
+        self.call(calls::trades(true))
+    }
+
+    /// Like `open_trades`, without restricting the result to opened trades.
+    pub fn all_trades(&mut self) -> Result<Vec<TradeRecord>> {
+        self.call(calls::trades(false))
+    }
+
+    pub fn trade_records(&mut self, orders: &[OrderId]) -> Result<Vec<TradeRecord>> {
+        self.call(calls::trade_records(orders))
+    }
+
+    pub fn news(&mut self, range: std::ops::Range<DateTime<Utc>>) -> Result<Vec<NewsRecord>> {
+        self.call(calls::news(range.start, range.end))
+    }
+
+    pub fn ib_history(&mut self, range: std::ops::Range<DateTime<Utc>>) -> Result<Vec<IbRecord>> {
+        self.call(calls::ib_history(range.start, range.end))
+    }
+
+    /// Trades closed in `range`, see `TradeHistory`.
+    pub fn history(
+        &mut self,
+        range: std::ops::Range<DateTime<Utc>>,
+    ) -> TradeHistory<impl FetchTrades + '_> {
+        TradeHistory::new(range, move |start, end| {
+            self.call(calls::trades_history(start, end))
+        })
+    }
+
+    pub fn trading_hours(&mut self, symbols: &[&str]) -> Result<Vec<TradingHoursRecord>> {
+        self.call(calls::trading_hours(symbols))
+    }
+
+    pub fn trading_calendar(&mut self, symbols: &[&str]) -> Result<TradingCalendar> {
+        Ok(TradingCalendar::new(self.trading_hours(symbols)?))
+    }
+
+    /// Makes `buy` and `sell` fail with `ErrorKind::MarketClosed` instead of
+    /// sending orders outside of the symbol's trading hours.
+    pub fn check_market_hours(&mut self, enabled: bool) {
+        self.market_hours = if enabled {
+            Some(self.market_hours.take().unwrap_or_default())
+        } else {
+            None
+        };
+    }
+
+    fn ensure_market_open(&mut self, symbol: &Symbol) -> Result<()> {
+        let known = match &self.market_hours {
+            Some(calendar) => calendar.contains(&symbol.0),
+            None => return Ok(()),
+        };
+        if !known {
+            let records = self.trading_hours(&[&symbol.0])?;
+            if let Some(calendar) = &mut self.market_hours {
+                calendar.extend(records);
+            }
         }
+
+        let now = self.server_now()?;
+        let calendar = self.market_hours.as_ref().unwrap();
+        calendar.ensure_open(&symbol.0, now)
     }
 
     pub fn buy<S: Into<Symbol>>(&mut self, symbol: S, volume: f64) -> Result<OrderId> {
         let symbol = symbol.into();
+        self.ensure_market_open(&symbol)?;
         self.send_transaction(symbol.buy(volume))
     }
 
     pub fn sell<S: Into<Symbol>>(&mut self, symbol: S, volume: f64) -> Result<OrderId> {
         let symbol = symbol.into();
+        self.ensure_market_open(&symbol)?;
         self.send_transaction(symbol.sell(volume))
     }
 
+    pub fn symbols(&mut self) -> Result<Vec<SymbolRecord>> {
+        self.call(calls::symbols())
+    }
+
+    pub fn symbol<S: Into<Symbol>>(&mut self, symbol: S) -> Result<SymbolRecord> {
+        self.call(calls::symbol(symbol.into()))
+    }
+
+    pub fn candles_range<S: Into<Symbol>>(
+        &mut self,
+        symbol: S,
+        period: Period,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Candle>> {
+        self.call(calls::candles_range(symbol.into(), period, from, to))
+    }
+
+    pub fn candles_since<S: Into<Symbol>>(
+        &mut self,
+        symbol: S,
+        period: Period,
+        start: DateTime<Utc>,
+    ) -> Result<Vec<Candle>> {
+        self.call(calls::candles_since(symbol.into(), period, start))
+    }
+
+    pub fn calendar(&mut self) -> Result<Vec<CalendarRecord>> {
+        self.call(calls::calendar())
+    }
+
+    pub fn commission<S: Into<Symbol>>(
+        &mut self,
+        symbol: S,
+        volume: f64,
+    ) -> Result<CommissionRecord> {
+        self.call(calls::commission(symbol.into(), volume))
+    }
+
+    pub fn margin_for<S: Into<Symbol>>(
+        &mut self,
+        symbol: S,
+        volume: f64,
+    ) -> Result<MarginTradeRecord> {
+        self.call(calls::margin_for(symbol.into(), volume))
+    }
+
+    pub fn margin_level(&mut self) -> Result<MarginLevelRecord> {
+        self.call(calls::margin_level())
+    }
+
+    /// Profit of a position, in the account currency, as the server would book it.
+    pub fn calculate_profit<S: Into<Symbol>>(
+        &mut self,
+        symbol: S,
+        side: OrderSide,
+        volume: f64,
+        open_price: f64,
+        close_price: f64,
+    ) -> Result<f64> {
+        let symbol = symbol.into();
+        self.call(calls::calculate_profit(
+            symbol,
+            side,
+            volume,
+            open_price,
+            close_price,
+        ))
+    }
+
+    pub fn server_time(&mut self) -> Result<DateTime<Utc>> {
+        self.call(calls::server_time())
+    }
+
+    /// Measures the offset between the local and the server clock.
+    pub fn sync_clock(&mut self) -> Result<&ServerClock> {
+        let sent = Utc::now();
+        let server_time = self.server_time()?;
+        let received = Utc::now();
+        Ok(self
+            .clock
+            .insert(ServerClock::from_sample(sent, received, server_time)))
+    }
+
+    /// The last clock measurement, if any.
+    pub fn clock(&self) -> Option<&ServerClock> {
+        self.clock.as_ref()
+    }
+
+    /// Current server time, re-measuring the clock offset when it gets old.
+    pub fn server_now(&mut self) -> Result<DateTime<Utc>> {
+        match &self.clock {
+            Some(clock) if clock.is_fresh() => Ok(clock.now()),
+            _ => Ok(self.sync_clock()?.now()),
+        }
+    }
+
+    pub fn step_rules(&mut self) -> Result<Vec<StepRule>> {
+        self.call(calls::step_rules())
+    }
+
+    /// Builds a rounder for `symbol`. Step rules are fetched once and then cached.
+    pub fn price_rounder(&mut self, symbol: &SymbolRecord) -> Result<PriceRounder> {
+        if self.step_rules.is_none() {
+            self.step_rules = Some(step::StepRules::new(self.step_rules()?));
+        }
+        Ok(self.step_rules.as_ref().unwrap().rounder(symbol))
+    }
+
+    /// Rounds the prices of `transaction` to its symbol's price steps.
+    pub fn round_prices(&mut self, transaction: &mut Transaction) -> Result<()> {
+        let record = self.symbol(transaction.symbol.unwrap_or_default())?;
+        let rounder = self.price_rounder(&record)?;
+        transaction.round_prices(&rounder);
+        Ok(())
+    }
+
+    fn send_pending(&mut self, mut transaction: Transaction) -> Result<OrderId> {
+        let record = self.symbol(transaction.symbol.unwrap_or_default())?;
+        let rounder = self.price_rounder(&record)?;
+        transaction.round_prices(&rounder);
+        transaction.check_market(record.bid, record.ask)?;
+        self.send_transaction(transaction)
+    }
+
+    pub fn place_limit<S: Into<Symbol>>(
+        &mut self,
+        symbol: S,
+        side: OrderSide,
+        volume: f64,
+        price: f64,
+        options: PendingOptions,
+    ) -> Result<OrderId> {
+        let symbol = symbol.into();
+        self.send_pending(symbol.pending(side.limit(), volume, price, &options)?)
+    }
+
+    pub fn place_stop<S: Into<Symbol>>(
+        &mut self,
+        symbol: S,
+        side: OrderSide,
+        volume: f64,
+        price: f64,
+        options: PendingOptions,
+    ) -> Result<OrderId> {
+        let symbol = symbol.into();
+        self.send_pending(symbol.pending(side.stop(), volume, price, &options)?)
+    }
+
     pub fn close<S: Into<Symbol>>(
         &mut self,
         symbol: S,
@@ -169,3 +641,11 @@ impl XTB {
         Ok(())
     }
 }
+
+impl Drop for XTB {
+    fn drop(&mut self) {
+        if let Err(e) = self.tear_down() {
+            log::warn!("Error while shutting down: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::time::{Duration, Instant};
+
+const DEFAULT_REQUEST_SPACING: Duration = Duration::from_millis(200);
+
+/// How fast messages may be sent on one connection.
+///
+/// Up to `burst` messages can go out back to back, after that one every
+/// `spacing`. The default is xAPI's own limit, anything faster gets the
+/// connection dropped by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub spacing: Duration,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(spacing: Duration, burst: u32) -> Self {
+        Self { spacing, burst }
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new(DEFAULT_REQUEST_SPACING, 1)
+    }
+}
+
+/// Token bucket enforcing a `RateLimit`.
+///
+/// Instead of counting tokens, this tracks when the bucket would be full again,
+/// which keeps the arithmetic exact.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    full_at: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            full_at: Instant::now(),
+        }
+    }
+
+    /// Takes a token, or returns how long it takes until the next one is available.
+    pub(crate) fn try_acquire(&mut self) -> Result<(), Duration> {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, now: Instant) -> Result<(), Duration> {
+        let spacing = self.limit.spacing;
+        let full_at = self.full_at.max(now);
+        // Taking a token must not push the refill more than a whole burst ahead
+        let burst = spacing * self.limit.burst.max(1);
+        let wait = (full_at + spacing).saturating_duration_since(now + burst);
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        self.full_at = full_at + spacing;
+        Ok(())
+    }
+
+    /// Takes a token, sleeping until one is available.
+    pub(crate) fn acquire(&mut self) {
+        while let Err(wait) = self.try_acquire() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACING: Duration = Duration::from_millis(200);
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn allows_a_burst_then_spaces_out() {
+        let mut limiter = RateLimiter::new(RateLimit::new(SPACING, 3));
+        let start = limiter.full_at;
+
+        assert_eq!(limiter.try_acquire_at(start), Ok(()));
+        assert_eq!(limiter.try_acquire_at(start), Ok(()));
+        assert_eq!(limiter.try_acquire_at(start), Ok(()));
+        assert_eq!(limiter.try_acquire_at(start), Err(SPACING));
+        assert_eq!(limiter.try_acquire_at(start + ms(50)), Err(ms(150)));
+        assert_eq!(limiter.try_acquire_at(start + SPACING), Ok(()));
+        assert_eq!(limiter.try_acquire_at(start + SPACING), Err(SPACING));
+    }
+
+    #[test]
+    fn refills_up_to_the_burst() {
+        let mut limiter = RateLimiter::new(RateLimit::new(SPACING, 2));
+        let start = limiter.full_at;
+        assert_eq!(limiter.try_acquire_at(start), Ok(()));
+        assert_eq!(limiter.try_acquire_at(start), Ok(()));
+
+        // A long pause doesn't save up more than `burst` tokens
+        let later = start + SPACING * 10;
+        assert_eq!(limiter.try_acquire_at(later), Ok(()));
+        assert_eq!(limiter.try_acquire_at(later), Ok(()));
+        assert_eq!(limiter.try_acquire_at(later), Err(SPACING));
+    }
+
+    #[test]
+    fn default_allows_one_request_every_200ms() {
+        let mut limiter = RateLimiter::new(RateLimit::default());
+        let start = limiter.full_at;
+        assert_eq!(limiter.try_acquire_at(start), Ok(()));
+        assert_eq!(limiter.try_acquire_at(start + ms(150)), Err(ms(50)));
+        assert_eq!(limiter.try_acquire_at(start + ms(200)), Ok(()));
+    }
+
+    #[test]
+    fn zero_spacing_is_unlimited() {
+        let mut limiter = RateLimiter::new(RateLimit::new(Duration::ZERO, 0));
+        let start = limiter.full_at;
+        for _ in 0..100 {
+            assert_eq!(limiter.try_acquire_at(start), Ok(()));
+        }
+    }
+}
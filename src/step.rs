@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::types::SymbolRecord;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Step {
+    /// Lowest price this step applies to.
+    #[serde(rename = "fromValue")]
+    pub from_value: f64,
+    pub step: f64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct StepRule {
+    pub id: i32,
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+impl StepRule {
+    /// The price increment that applies at `price`.
+    pub fn step_for(&self, price: f64) -> Option<f64> {
+        self.steps
+            .iter()
+            .filter(|step| step.from_value <= price)
+            .max_by(|a, b| a.from_value.total_cmp(&b.from_value))
+            .map(|step| step.step)
+    }
+}
+
+/// Step rules by id, as cached by the clients.
+pub(crate) struct StepRules(HashMap<i32, StepRule>);
+
+impl StepRules {
+    pub(crate) fn new(rules: Vec<StepRule>) -> Self {
+        Self(rules.into_iter().map(|rule| (rule.id, rule)).collect())
+    }
+
+    pub(crate) fn rounder(&self, symbol: &SymbolRecord) -> PriceRounder {
+        PriceRounder::new(symbol, self.0.get(&symbol.step_rule_id))
+    }
+}
+
+/// Rounds prices to the closest value the server accepts for a symbol.
+#[derive(Clone, Debug)]
+pub struct PriceRounder {
+    steps: Option<StepRule>,
+    tick_size: f64,
+    precision: u32,
+}
+
+impl PriceRounder {
+    /// Uses the symbol's step rule when it has one, its tick size otherwise.
+    pub fn new(symbol: &SymbolRecord, rule: Option<&StepRule>) -> Self {
+        Self {
+            steps: rule.filter(|rule| !rule.steps.is_empty()).cloned(),
+            tick_size: symbol.tick_size,
+            precision: symbol.precision,
+        }
+    }
+
+    pub fn step_for(&self, price: f64) -> f64 {
+        self.steps
+            .as_ref()
+            .and_then(|rule| rule.step_for(price))
+            .unwrap_or(self.tick_size)
+    }
+
+    pub fn round(&self, price: f64) -> f64 {
+        let step = self.step_for(price);
+        let rounded = if step > 0.0 {
+            (price / step).round() * step
+        } else {
+            price
+        };
+        // Get rid of the floating point noise left by the division
+        let scale = 10f64.powi(self.precision as i32);
+        (rounded * scale).round() / scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(steps: &[(f64, f64)]) -> StepRule {
+        StepRule {
+            id: 1,
+            name: "test".to_owned(),
+            steps: steps
+                .iter()
+                .map(|&(from_value, step)| Step { from_value, step })
+                .collect(),
+        }
+    }
+
+    fn rounder(steps: Option<StepRule>, tick_size: f64, precision: u32) -> PriceRounder {
+        PriceRounder {
+            steps,
+            tick_size,
+            precision,
+        }
+    }
+
+    #[test]
+    fn picks_the_step_of_the_price_tier() {
+        let rule = rule(&[(1000.0, 0.5), (0.0, 0.01), (100.0, 0.05)]);
+        assert_eq!(rule.step_for(50.0), Some(0.01));
+        assert_eq!(rule.step_for(100.0), Some(0.05));
+        assert_eq!(rule.step_for(999.99), Some(0.05));
+        assert_eq!(rule.step_for(1000.0), Some(0.5));
+    }
+
+    #[test]
+    fn falls_back_to_the_tick_size() {
+        let stepped = rounder(Some(rule(&[(10.0, 0.5)])), 0.01, 2);
+        assert_eq!(stepped.step_for(5.0), 0.01);
+        assert_eq!(stepped.round(5.123), 5.12);
+        assert_eq!(stepped.round(12.3), 12.5);
+
+        let plain = rounder(None, 0.25, 2);
+        assert_eq!(plain.round(1.3), 1.25);
+        assert_eq!(plain.round(1.4), 1.5);
+    }
+
+    #[test]
+    fn rounds_across_tier_boundaries() {
+        let rounder = rounder(Some(rule(&[(0.0, 0.01), (100.0, 0.05)])), 0.01, 2);
+        assert_eq!(rounder.round(99.994), 99.99);
+        assert_eq!(rounder.round(100.07), 100.05);
+        assert_eq!(rounder.round(100.08), 100.1);
+    }
+
+    #[test]
+    fn cleans_up_float_noise() {
+        // 3 * 0.1 is 0.30000000000000004 without the cleanup
+        assert_eq!(rounder(None, 0.1, 1).round(0.29), 0.3);
+        assert_eq!(rounder(None, 0.0001, 4).round(1.23456), 1.2346);
+        assert_eq!(rounder(None, 0.0, 3).round(1.23456), 1.235);
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::commands::*;
+use crate::json_socket::JsonSocket;
+use crate::rate_limit::{RateLimit, RateLimiter};
+use crate::Result;
+
+/// A stream that can be subscribed to on the streaming connection.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum StreamKind {
+    Balance,
+    Trades,
+    Profits,
+    TradeStatus,
+    Candles(String),
+    TickPrices(String),
+    News,
+    KeepAlive,
+}
+
+impl StreamKind {
+    pub(crate) fn stop_command(&self) -> StreamingCommandType {
+        match self {
+            StreamKind::Balance => StreamingCommandType::StopBalance,
+            StreamKind::Trades => StreamingCommandType::StopTrades,
+            StreamKind::Profits => StreamingCommandType::StopProfits,
+            StreamKind::TradeStatus => StreamingCommandType::StopTradeStatus,
+            StreamKind::Candles(symbol) => StreamingCommandType::StopCandles {
+                symbol: symbol.clone(),
+            },
+            StreamKind::TickPrices(symbol) => StreamingCommandType::StopTickPrices {
+                symbol: symbol.clone(),
+            },
+            StreamKind::News => StreamingCommandType::StopNews,
+            StreamKind::KeepAlive => StreamingCommandType::StopKeepAlive,
+        }
+    }
+}
+
+impl StreamingMessage {
+    /// The stream this message was sent on, `None` for locally generated events.
+    pub fn kind(&self) -> Option<StreamKind> {
+        let kind = match self {
+            StreamingMessage::Balance { .. } => StreamKind::Balance,
+            StreamingMessage::Trade { .. } => StreamKind::Trades,
+            StreamingMessage::Profit { .. } => StreamKind::Profits,
+            StreamingMessage::Candles { data } => StreamKind::Candles(data.symbol.clone()),
+            StreamingMessage::TradeStatus { .. } => StreamKind::TradeStatus,
+            StreamingMessage::TickPrices { data } => StreamKind::TickPrices(data.symbol.clone()),
+            StreamingMessage::KeepAlive { .. } => StreamKind::KeepAlive,
+            StreamingMessage::News { .. } => StreamKind::News,
+            StreamingMessage::ConnectionStale { .. } => return None,
+        };
+        Some(kind)
+    }
+}
+
+/// How many subscriptions there are for each running stream.
+#[derive(Default)]
+pub(crate) struct ActiveStreams(Mutex<HashMap<StreamKind, usize>>);
+
+impl ActiveStreams {
+    pub(crate) fn acquire(&self, kind: StreamKind) {
+        *self.0.lock().unwrap().entry(kind).or_insert(0) += 1;
+    }
+
+    /// Drops one reference to `kind`, returns whether it was the last one.
+    pub(crate) fn release(&self, kind: &StreamKind) -> bool {
+        let mut active = self.0.lock().unwrap();
+        match active.get_mut(kind) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            Some(_) => {
+                active.remove(kind);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn contains(&self, kind: &StreamKind) -> bool {
+        self.0.lock().unwrap().contains_key(kind)
+    }
+
+    pub(crate) fn kinds(&self) -> Vec<StreamKind> {
+        self.0.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Forgets every stream, returning the ones that were running.
+    pub(crate) fn drain(&self) -> Vec<StreamKind> {
+        self.0.lock().unwrap().drain().map(|(k, _)| k).collect()
+    }
+}
+
+/// A streaming connection that subscriptions can be handed back to.
+pub(crate) trait StreamRelease: Send + Sync {
+    /// Drops one reference to `kind`, stopping the stream once nobody is subscribed to it.
+    fn release(&self, kind: &StreamKind) -> Result<()>;
+}
+
+/// The streaming socket, shared between the client, the reader thread and the subscriptions.
+pub(crate) struct StreamingConnection {
+    pub(crate) socket: Mutex<JsonSocket>,
+    /// Kept apart from the socket, so waiting for it doesn't block the reader thread.
+    limiter: Mutex<RateLimiter>,
+    session_id: String,
+    active: ActiveStreams,
+    closed: AtomicBool,
+}
+
+impl StreamingConnection {
+    pub(crate) fn new(socket: JsonSocket, session_id: String, rate_limit: RateLimit) -> Self {
+        Self {
+            socket: Mutex::new(socket),
+            limiter: Mutex::new(RateLimiter::new(rate_limit)),
+            session_id,
+            active: ActiveStreams::default(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn send(&self, command: StreamingCommandType) -> Result<()> {
+        let cmd = StreamingCommand::new(command, self.session_id.clone());
+        self.limiter.lock().unwrap().acquire();
+        self.socket.lock().unwrap().send(&cmd)
+    }
+
+    pub(crate) fn subscribe(
+        self: &Arc<Self>,
+        kind: StreamKind,
+        command: StreamingCommandType,
+    ) -> Result<Subscription> {
+        // Marked active first, so nothing sent right after the command gets dropped
+        self.active.acquire(kind.clone());
+        if let Err(e) = self.send(command) {
+            self.active.release(&kind);
+            return Err(e);
+        }
+        Ok(Subscription::new(kind, self.clone()))
+    }
+
+    pub(crate) fn is_active(&self, kind: &StreamKind) -> bool {
+        self.active.contains(kind)
+    }
+
+    pub(crate) fn active(&self) -> Vec<StreamKind> {
+        self.active.kinds()
+    }
+
+    /// Stops every stream, whether or not there are subscriptions left for it.
+    /// Every stop command is sent, even if an earlier one failed.
+    pub(crate) fn stop_all(&self) -> Result<()> {
+        let mut result = Ok(());
+        for kind in self.active.drain() {
+            let sent = self.send(kind.stop_command());
+            result = result.and(sent);
+        }
+        result
+    }
+
+    /// Tells the reader thread to exit.
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+impl StreamRelease for StreamingConnection {
+    fn release(&self, kind: &StreamKind) -> Result<()> {
+        if self.active.release(kind) {
+            self.send(kind.stop_command())?;
+        }
+        Ok(())
+    }
+}
+
+/// A running stream. The matching stop command is sent when the last handle
+/// for the stream is dropped or unsubscribed.
+#[must_use = "the stream is stopped as soon as the subscription is dropped"]
+pub struct Subscription {
+    kind: StreamKind,
+    connection: Option<Arc<dyn StreamRelease>>,
+}
+
+impl Subscription {
+    pub(crate) fn new(kind: StreamKind, connection: Arc<dyn StreamRelease>) -> Self {
+        Self {
+            kind,
+            connection: Some(connection),
+        }
+    }
+
+    pub fn kind(&self) -> &StreamKind {
+        &self.kind
+    }
+
+    pub fn unsubscribe(mut self) -> Result<()> {
+        match self.connection.take() {
+            Some(connection) => connection.release(&self.kind),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            if let Err(e) = connection.release(&self.kind) {
+                log::warn!("Failed to stop {:?} stream: {:?}", self.kind, e);
+            }
+        }
+    }
+}
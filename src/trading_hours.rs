@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::CET;
+use serde::Deserialize;
+
+use crate::{ErrorKind, Result};
+
+/// A session on one day of the week. Times are milliseconds after midnight, server (CET/CEST) time.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SessionRecord {
+    /// Day of the week, 1 being Monday.
+    pub day: u32,
+    #[serde(rename = "fromT")]
+    pub from_t: u64,
+    #[serde(rename = "toT")]
+    pub to_t: u64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TradingHoursRecord {
+    pub symbol: String,
+    pub quotes: Vec<SessionRecord>,
+    pub trading: Vec<SessionRecord>,
+}
+
+/// Answers when symbols can be traded, based on their trading hours.
+#[derive(Default, Clone, Debug)]
+pub struct TradingCalendar {
+    symbols: HashMap<String, TradingHoursRecord>,
+}
+
+/// How far ahead to look for sessions. A week covers every weekday, the extra day
+/// lets sessions running over the end of the week be merged.
+const LOOKAHEAD_DAYS: i64 = 8;
+
+impl TradingCalendar {
+    pub fn new(records: Vec<TradingHoursRecord>) -> Self {
+        let mut calendar = Self::default();
+        calendar.extend(records);
+        calendar
+    }
+
+    pub fn extend(&mut self, records: Vec<TradingHoursRecord>) {
+        self.symbols
+            .extend(records.into_iter().map(|r| (r.symbol.clone(), r)));
+    }
+
+    pub fn contains(&self, symbol: &str) -> bool {
+        self.symbols.contains_key(symbol)
+    }
+
+    /// `None` if the symbol's trading hours aren't known.
+    pub fn is_open(&self, symbol: &str, at: DateTime<Utc>) -> Option<bool> {
+        let sessions = self.sessions(symbol, at)?;
+        Some(sessions.iter().any(|(from, to)| *from <= at && at < *to))
+    }
+
+    /// Fails with `ErrorKind::MarketClosed` if `symbol` is known to be closed at `at`.
+    pub(crate) fn ensure_open(&self, symbol: &str, at: DateTime<Utc>) -> Result<()> {
+        match self.is_open(symbol, at) {
+            Some(false) => Err(ErrorKind::MarketClosed(
+                symbol.to_owned(),
+                self.next_open_after(symbol, at),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn next_open(&self, symbol: &str) -> Option<DateTime<Utc>> {
+        self.next_open_after(symbol, Utc::now())
+    }
+
+    pub fn next_close(&self, symbol: &str) -> Option<DateTime<Utc>> {
+        self.next_close_after(symbol, Utc::now())
+    }
+
+    /// Start of the first session after `at`. If the market is open at `at`,
+    /// that's the session after the current one.
+    pub fn next_open_after(&self, symbol: &str, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.sessions(symbol, at)?
+            .into_iter()
+            .map(|(from, _)| from)
+            .find(|from| *from > at)
+    }
+
+    /// End of the current session, or of the next one if the market is closed at `at`.
+    pub fn next_close_after(&self, symbol: &str, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.sessions(symbol, at)?
+            .into_iter()
+            .map(|(_, to)| to)
+            .find(|to| *to > at)
+    }
+
+    /// Trading sessions from the day of `at` on, merged where one ends when the next begins.
+    fn sessions(
+        &self,
+        symbol: &str,
+        at: DateTime<Utc>,
+    ) -> Option<Vec<(DateTime<Utc>, DateTime<Utc>)>> {
+        let record = self.symbols.get(symbol)?;
+        let first_day = at.with_timezone(&CET).date_naive();
+
+        let mut sessions: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+        for offset in 0..LOOKAHEAD_DAYS {
+            let day = first_day + Duration::days(offset);
+            let mut today: Vec<_> = record
+                .trading
+                .iter()
+                .filter(|s| s.day == day.weekday().number_from_monday())
+                .map(|s| (server_time(day, s.from_t), server_time(day, s.to_t)))
+                .collect();
+            today.sort();
+
+            for (from, to) in today {
+                match sessions.last_mut() {
+                    Some((_, end)) if *end >= from => *end = (*end).max(to),
+                    _ => sessions.push((from, to)),
+                }
+            }
+        }
+        Some(sessions)
+    }
+}
+
+fn server_time(day: NaiveDate, millis: u64) -> DateTime<Utc> {
+    let local = day.and_hms_opt(0, 0, 0).unwrap() + Duration::milliseconds(millis as i64);
+    match CET.from_local_datetime(&local).earliest() {
+        Some(time) => time.with_timezone(&Utc),
+        // Skipped by a DST change, the clock jumps forward by an hour
+        None => CET
+            .from_local_datetime(&(local + Duration::hours(1)))
+            .earliest()
+            .unwrap()
+            .with_timezone(&Utc),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60 * 1000;
+    const HOUR: u64 = 60 * 60 * 1000;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn calendar(symbol: &str, trading: Vec<SessionRecord>) -> TradingCalendar {
+        TradingCalendar::new(vec![TradingHoursRecord {
+            symbol: symbol.to_owned(),
+            quotes: trading.clone(),
+            trading,
+        }])
+    }
+
+    fn whole_days(days: std::ops::RangeInclusive<u32>) -> Vec<SessionRecord> {
+        days.map(|day| SessionRecord {
+            day,
+            from_t: 0,
+            to_t: DAY,
+        })
+        .collect()
+    }
+
+    #[test]
+    fn always_open_symbol_is_one_merged_session() {
+        let calendar = calendar("BITCOIN", whole_days(1..=7));
+        let saturday = utc("2024-01-13T12:00:00Z");
+
+        assert_eq!(calendar.is_open("BITCOIN", saturday), Some(true));
+        assert_eq!(calendar.sessions("BITCOIN", saturday).unwrap().len(), 1);
+        assert_eq!(calendar.next_open_after("BITCOIN", saturday), None);
+        assert_eq!(calendar.is_open("EURUSD", saturday), None);
+    }
+
+    #[test]
+    fn fx_symbol_is_closed_over_the_weekend() {
+        let calendar = calendar("EURUSD", whole_days(1..=5));
+
+        let friday = utc("2024-01-12T12:00:00Z");
+        assert_eq!(calendar.is_open("EURUSD", friday), Some(true));
+        // Midnight CET, an hour earlier in UTC
+        assert_eq!(
+            calendar.next_close_after("EURUSD", friday),
+            Some(utc("2024-01-12T23:00:00Z"))
+        );
+
+        let saturday = utc("2024-01-13T12:00:00Z");
+        assert_eq!(calendar.is_open("EURUSD", saturday), Some(false));
+        assert_eq!(
+            calendar.next_open_after("EURUSD", saturday),
+            Some(utc("2024-01-14T23:00:00Z"))
+        );
+        assert_eq!(
+            calendar.next_close_after("EURUSD", saturday),
+            Some(utc("2024-01-19T23:00:00Z"))
+        );
+
+        // Monday to Friday runs together into one session
+        let monday = utc("2024-01-15T08:00:00Z");
+        assert_eq!(
+            calendar.next_close_after("EURUSD", monday),
+            Some(utc("2024-01-19T23:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn spring_forward_skips_the_missing_hour() {
+        let sunday = NaiveDate::from_ymd_opt(2024, 3, 31).unwrap();
+        // 02:30 doesn't exist that night, it's 03:30 CEST
+        assert_eq!(
+            server_time(sunday, 2 * HOUR + HOUR / 2),
+            utc("2024-03-31T01:30:00Z")
+        );
+        assert_eq!(server_time(sunday, 4 * HOUR), utc("2024-03-31T02:00:00Z"));
+
+        let calendar = calendar("DE30", whole_days(7..=7));
+        let at = utc("2024-03-31T12:00:00Z");
+        let sessions = calendar.sessions("DE30", at).unwrap();
+        assert_eq!(
+            sessions[0],
+            (utc("2024-03-30T23:00:00Z"), utc("2024-03-31T22:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn fall_back_uses_the_first_of_the_repeated_hour() {
+        let sunday = NaiveDate::from_ymd_opt(2024, 10, 27).unwrap();
+        // 02:30 happens twice, first in CEST
+        assert_eq!(
+            server_time(sunday, 2 * HOUR + HOUR / 2),
+            utc("2024-10-27T00:30:00Z")
+        );
+
+        let calendar = calendar("DE30", whole_days(7..=7));
+        let at = utc("2024-10-27T12:00:00Z");
+        assert_eq!(calendar.is_open("DE30", at), Some(true));
+        assert_eq!(
+            calendar.next_close_after("DE30", at),
+            Some(utc("2024-10-27T23:00:00Z"))
+        );
+        assert_eq!(
+            calendar.next_open_after("DE30", at),
+            Some(utc("2024-11-02T23:00:00Z"))
+        );
+    }
+}
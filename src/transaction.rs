@@ -1,8 +1,11 @@
 use derive_builder::Builder;
 
+use crate::step::PriceRounder;
 use crate::types::*;
+use crate::{ErrorKind, Result};
 
 #[derive(Default, Builder, Debug)]
+#[builder(build_fn(validate = "Self::validate"))]
 pub struct Transaction<'a> {
     #[builder(setter(into, strip_option))]
     pub kind: Option<OrderType>,
@@ -18,20 +21,134 @@ pub struct Transaction<'a> {
     #[builder(default)]
     pub typ: usize,
 
+    /// Expiration of a pending order, in milliseconds since the epoch.
+    #[builder(setter(into, strip_option), default)]
+    pub expiration: Option<u64>,
+    /// Trailing offset in points, `0` disables it.
     #[builder(default)]
-    expiration: u64,
-    #[builder(default)]
-    offset: usize,
-    #[builder(default)]
-    tp: f64,
+    pub offset: usize,
+    /// Take profit, `0.0` means none.
     #[builder(default)]
-    sl: f64,
+    pub tp: f64,
+    /// Stop loss, `0.0` means none.
     #[builder(default)]
-    price: f64,
+    pub sl: f64,
+    /// Order price. Ignored by the server for market orders, but it has to be sent anyway.
+    #[builder(default = "1.0")]
+    pub price: f64,
+}
+
+impl<'a> TransactionBuilder<'a> {
+    fn validate(&self) -> std::result::Result<(), String> {
+        if let Some(volume) = self.volume {
+            if volume <= 0.0 {
+                return Err(format!("volume must be positive, got {}", volume));
+            }
+        }
+
+        let kind = self.kind.flatten();
+        let side = self.side.flatten();
+        match (kind, side) {
+            (Some(OrderType::Pending), Some(side)) if !side.is_pending() => {
+                return Err(format!("{:?} is not a pending order side", side));
+            }
+            (Some(OrderType::Pending), None) => {
+                return Err("pending orders need a side".to_owned());
+            }
+            (Some(OrderType::Open), Some(side)) if side.is_pending() => {
+                return Err(format!("{:?} can only be used with pending orders", side));
+            }
+            _ => {}
+        }
+
+        if kind != Some(OrderType::Pending) {
+            return Ok(());
+        }
+
+        let price = self.price.unwrap_or_default();
+        if price <= 0.0 {
+            return Err("pending orders need a price".to_owned());
+        }
+
+        let is_buy = side.map(OrderSide::is_buy).unwrap_or(true);
+        let sl = self.sl.unwrap_or_default();
+        let tp = self.tp.unwrap_or_default();
+        if sl != 0.0 && (sl < price) != is_buy {
+            return Err(format!(
+                "stop loss {} is on the wrong side of the order price {}",
+                sl, price
+            ));
+        }
+        if tp != 0.0 && (tp > price) != is_buy {
+            return Err(format!(
+                "take profit {} is on the wrong side of the order price {}",
+                tp, price
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Transaction<'a> {
+    /// Rounds the order price (for pending orders), stop loss and take profit
+    /// to values the server accepts.
+    pub fn round_prices(&mut self, rounder: &PriceRounder) {
+        if self.kind == Some(OrderType::Pending) {
+            self.price = rounder.round(self.price);
+        }
+        if self.sl != 0.0 {
+            self.sl = rounder.round(self.sl);
+        }
+        if self.tp != 0.0 {
+            self.tp = rounder.round(self.tp);
+        }
+    }
+
+    /// Checks that a pending order's price is on the correct side of the current market.
+    ///
+    /// Limit orders have to be placed below the ask (buy) or above the bid (sell),
+    /// stop orders the other way round. Market orders are always accepted.
+    pub fn check_market(&self, bid: f64, ask: f64) -> Result<()> {
+        let ok = match self.side.unwrap_or_default() {
+            OrderSide::Buy | OrderSide::Sell => true,
+            OrderSide::BuyLimit => self.price < ask,
+            OrderSide::SellLimit => self.price > bid,
+            OrderSide::BuyStop => self.price > ask,
+            OrderSide::SellStop => self.price < bid,
+        };
+
+        if ok {
+            Ok(())
+        } else {
+            Err(ErrorKind::InvalidTransaction(format!(
+                "{:?} price {} is on the wrong side of the market (bid {}, ask {})",
+                self.side.unwrap_or_default(),
+                self.price,
+                bid,
+                ask
+            )))
+        }
+    }
+}
+
+impl From<TransactionBuilderError> for ErrorKind {
+    fn from(value: TransactionBuilderError) -> Self {
+        ErrorKind::InvalidTransaction(value.to_string())
+    }
+}
+
+/// Optional parameters of a pending order.
+#[derive(Default, Clone, Debug)]
+pub struct PendingOptions {
+    pub sl: Option<f64>,
+    pub tp: Option<f64>,
+    /// Milliseconds since the epoch, `None` keeps the order until cancelled.
+    pub expiration: Option<u64>,
 }
 
 impl Symbol {
-    pub fn buy(&self, volume: f64) -> Transaction {
+    pub fn buy(&self, volume: f64) -> Transaction<'_> {
         TransactionBuilder::default()
             .symbol(self.0.as_ref())
             .side(OrderSide::Buy)
@@ -41,7 +158,7 @@ impl Symbol {
             .unwrap()
     }
 
-    pub fn sell(&self, volume: f64) -> Transaction {
+    pub fn sell(&self, volume: f64) -> Transaction<'_> {
         TransactionBuilder::default()
             .symbol(self.0.as_ref())
             .kind(OrderType::Open)
@@ -51,7 +168,7 @@ impl Symbol {
             .unwrap()
     }
 
-    pub fn close(&self, position: PositionId, volume: f64) -> Transaction {
+    pub fn close(&self, position: PositionId, volume: f64) -> Transaction<'_> {
         TransactionBuilder::default()
             .symbol(self.0.as_ref())
             .kind(OrderType::Close)
@@ -61,4 +178,108 @@ impl Symbol {
             .build()
             .unwrap()
     }
+
+    /// Builds a pending order. `side` has to be one of the limit or stop sides.
+    pub fn pending(
+        &self,
+        side: OrderSide,
+        volume: f64,
+        price: f64,
+        options: &PendingOptions,
+    ) -> Result<Transaction<'_>> {
+        let mut builder = TransactionBuilder::default();
+        builder
+            .symbol(self.0.as_ref())
+            .kind(OrderType::Pending)
+            .side(side)
+            .volume(volume)
+            .price(price)
+            .sl(options.sl.unwrap_or_default())
+            .tp(options.tp.unwrap_or_default());
+        if let Some(expiration) = options.expiration {
+            builder.expiration(expiration);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(side: OrderSide, price: f64, sl: f64, tp: f64) -> Result<Transaction<'static>> {
+        Ok(TransactionBuilder::default()
+            .symbol("EURUSD")
+            .kind(OrderType::Pending)
+            .side(side)
+            .volume(0.1)
+            .price(price)
+            .sl(sl)
+            .tp(tp)
+            .build()?)
+    }
+
+    #[test]
+    fn rejects_non_positive_volume() {
+        let result = TransactionBuilder::default()
+            .kind(OrderType::Open)
+            .side(OrderSide::Buy)
+            .volume(0.0)
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn pending_orders_need_a_pending_side_and_price() {
+        assert!(pending(OrderSide::Buy, 1.1, 0.0, 0.0).is_err());
+        assert!(pending(OrderSide::BuyLimit, 0.0, 0.0, 0.0).is_err());
+        assert!(pending(OrderSide::BuyLimit, 1.1, 0.0, 0.0).is_ok());
+
+        let missing_side = TransactionBuilder::default()
+            .kind(OrderType::Pending)
+            .volume(0.1)
+            .price(1.1)
+            .build();
+        assert!(missing_side.is_err());
+
+        let market_with_pending_side = TransactionBuilder::default()
+            .kind(OrderType::Open)
+            .side(OrderSide::SellStop)
+            .volume(0.1)
+            .build();
+        assert!(market_with_pending_side.is_err());
+    }
+
+    #[test]
+    fn sl_and_tp_must_be_on_the_right_side() {
+        assert!(pending(OrderSide::BuyLimit, 1.1, 1.0, 1.2).is_ok());
+        assert!(pending(OrderSide::BuyLimit, 1.1, 1.2, 0.0).is_err());
+        assert!(pending(OrderSide::BuyLimit, 1.1, 0.0, 1.0).is_err());
+
+        assert!(pending(OrderSide::SellStop, 1.1, 1.2, 1.0).is_ok());
+        assert!(pending(OrderSide::SellStop, 1.1, 1.0, 0.0).is_err());
+        assert!(pending(OrderSide::SellStop, 1.1, 0.0, 1.2).is_err());
+    }
+
+    #[test]
+    fn checks_pending_price_against_the_market() {
+        let (bid, ask) = (1.0998, 1.1000);
+        let check = |side, price| {
+            pending(side, price, 0.0, 0.0)
+                .unwrap()
+                .check_market(bid, ask)
+        };
+
+        assert!(check(OrderSide::BuyLimit, 1.09).is_ok());
+        assert!(check(OrderSide::BuyLimit, 1.11).is_err());
+        assert!(check(OrderSide::SellLimit, 1.11).is_ok());
+        assert!(check(OrderSide::SellLimit, 1.09).is_err());
+        assert!(check(OrderSide::BuyStop, 1.11).is_ok());
+        assert!(check(OrderSide::BuyStop, 1.09).is_err());
+        assert!(check(OrderSide::SellStop, 1.09).is_ok());
+        assert!(check(OrderSide::SellStop, 1.11).is_err());
+
+        let market = Symbol("EURUSD".to_owned());
+        assert!(market.buy(0.1).check_market(bid, ask).is_ok());
+    }
 }
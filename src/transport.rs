@@ -0,0 +1,298 @@
+use once_cell::sync::Lazy;
+use rustls::{ClientConfig, ClientSession, Session, StreamOwned};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::ErrorKind;
+use crate::Result;
+
+pub(crate) static RUSTLS_CLIENT_CONFIG: Lazy<Arc<ClientConfig>> = Lazy::new(|| {
+    let mut config = ClientConfig::new();
+    let roots: &webpki::TLSServerTrustAnchors = &webpki_roots::TLS_SERVER_ROOTS;
+    config.root_store.add_server_trust_anchors(roots);
+    std::sync::Arc::new(config)
+});
+
+/// Moves whole messages to and from the server.
+pub trait Transport: Send {
+    fn send_message(&mut self, message: &str) -> Result<()>;
+    /// Fails with `ErrorKind::WouldBlock` if the transport is non-blocking and
+    /// no complete message is available yet.
+    fn recv_message(&mut self) -> Result<String>;
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()>;
+    /// Makes receiving fail with `ErrorKind::Timeout` when nothing arrives in time.
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()>;
+    fn close(&mut self) -> Result<()>;
+}
+
+/// Shuts the socket down, treating a peer that already hung up as success.
+pub(crate) fn shutdown_tcp(socket: &TcpStream) -> Result<()> {
+    match socket.shutdown(std::net::Shutdown::Both) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotConnected => Err(ErrorKind::Disconnected(e)),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn connect_tcp(host: &str, port: u16, timeout: Option<Duration>) -> Result<TcpStream> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return TcpStream::connect((host, port)).map_err(ErrorKind::Disconnected),
+    };
+
+    let mut last_error = None;
+    for addr in (host, port)
+        .to_socket_addrs()
+        .map_err(ErrorKind::Disconnected)?
+    {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(socket) => return Ok(socket),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(ErrorKind::Disconnected(last_error.unwrap_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "host has no addresses")
+    })))
+}
+
+/// The name the server certificate is checked against. IP addresses aren't supported.
+pub(crate) fn dns_name(host: &str) -> Result<webpki::DNSNameRef<'_>> {
+    webpki::DNSNameRef::try_from_ascii_str(host)
+        .map_err(|_| ErrorKind::InvalidConfig(format!("invalid host name {}", host)))
+}
+
+/// Wraps `socket` in TLS and completes the handshake.
+///
+/// The handshake has to finish while the socket still blocks, on a
+/// non-blocking socket the first write would fail with `WouldBlock`.
+pub(crate) fn tls_stream(
+    config: &Arc<ClientConfig>,
+    dns_name: webpki::DNSNameRef<'_>,
+    mut socket: TcpStream,
+) -> Result<StreamOwned<ClientSession, TcpStream>> {
+    let mut client = ClientSession::new(config, dns_name);
+    while client.is_handshaking() {
+        client
+            .complete_io(&mut socket)
+            .map_err(ErrorKind::Disconnected)?;
+    }
+    Ok(rustls::StreamOwned::new(client, socket))
+}
+
+pub(crate) fn read_error(e: std::io::Error, nonblocking: bool) -> ErrorKind {
+    match e.kind() {
+        std::io::ErrorKind::WouldBlock if nonblocking => ErrorKind::WouldBlock,
+        // A read timeout shows up as either, depending on the platform
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => ErrorKind::Timeout,
+        _ => ErrorKind::Disconnected(e),
+    }
+}
+
+/// Reads the next non-empty line, keeping a partly read one in `line` for the next call.
+fn read_message<R: BufRead>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+    nonblocking: bool,
+) -> Result<String> {
+    loop {
+        // Whatever was read before an error stays in `line`
+        match reader.read_until(b'\n', line) {
+            Ok(_) if !line.ends_with(b"\n") => {
+                return Err(ErrorKind::Disconnected(
+                    std::io::ErrorKind::UnexpectedEof.into(),
+                ))
+            }
+            // Messages are separated by an empty line
+            Ok(_) if line.iter().all(u8::is_ascii_whitespace) => line.clear(),
+            Ok(_) => {
+                let message = std::mem::take(line);
+                return String::from_utf8(message).map_err(|_| ErrorKind::InvalidResponse);
+            }
+            Err(e) => return Err(read_error(e, nonblocking)),
+        }
+    }
+}
+
+/// xAPI's own protocol: JSON over TLS, every message followed by an empty line.
+pub struct TlsTransport {
+    stream: BufReader<StreamOwned<ClientSession, TcpStream>>,
+    line: Vec<u8>,
+    nonblocking: bool,
+}
+
+impl TlsTransport {
+    pub fn connect(host: &str, port: u16, timeout: Option<Duration>) -> Result<Self> {
+        Self::connect_with(host, port, timeout, &RUSTLS_CLIENT_CONFIG)
+    }
+
+    pub(crate) fn connect_with(
+        host: &str,
+        port: u16,
+        timeout: Option<Duration>,
+        config: &Arc<ClientConfig>,
+    ) -> Result<Self> {
+        let dns_name = dns_name(host)?;
+        let socket = connect_tcp(host, port, timeout)?;
+        // Don't let a silent server stall the handshake past the connect timeout
+        socket
+            .set_read_timeout(timeout)
+            .map_err(ErrorKind::Disconnected)?;
+        let stream = tls_stream(config, dns_name, socket)?;
+        stream
+            .sock
+            .set_read_timeout(None)
+            .map_err(ErrorKind::Disconnected)?;
+        Ok(Self {
+            stream: BufReader::new(stream),
+            line: Vec::new(),
+            nonblocking: false,
+        })
+    }
+}
+
+impl Transport for TlsTransport {
+    fn send_message(&mut self, message: &str) -> Result<()> {
+        self.stream
+            .get_mut()
+            .write_all(message.as_bytes())
+            .map_err(ErrorKind::Disconnected)
+    }
+
+    fn recv_message(&mut self) -> Result<String> {
+        read_message(&mut self.stream, &mut self.line, self.nonblocking)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        self.stream
+            .get_mut()
+            .sock
+            .set_nonblocking(nonblocking)
+            .map_err(ErrorKind::Disconnected)?;
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.stream
+            .get_mut()
+            .sock
+            .set_read_timeout(timeout)
+            .map_err(ErrorKind::Disconnected)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let stream = self.stream.get_mut();
+        stream.sess.send_close_notify();
+        // Best effort, the server may already be gone
+        let _ = stream.flush();
+        shutdown_tcp(&stream.sock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out one chunk per read, `None` being a `WouldBlock`.
+    struct Chunks(Vec<Option<&'static [u8]>>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            match self.0.remove(0) {
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    Ok(chunk.len())
+                }
+                None => Err(std::io::ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_partial_messages_across_would_block() {
+        let mut reader = BufReader::new(Chunks(vec![
+            Some(b"{\"a\":"),
+            None,
+            Some(b"1}\n"),
+            None,
+            Some(b"\n{\"b\":2}"),
+            None,
+            Some(b"\n\n"),
+        ]));
+        let mut line = Vec::new();
+        let mut read = || read_message(&mut reader, &mut line, true);
+
+        assert!(matches!(read(), Err(ErrorKind::WouldBlock)));
+        assert_eq!(read().unwrap(), "{\"a\":1}\n");
+        assert!(matches!(read(), Err(ErrorKind::WouldBlock)));
+        assert!(matches!(read(), Err(ErrorKind::WouldBlock)));
+        assert_eq!(read().unwrap(), "{\"b\":2}\n");
+        assert!(matches!(read(), Err(ErrorKind::Disconnected(_))));
+    }
+
+    #[test]
+    fn rejects_ip_hosts_before_connecting() {
+        // Nothing listens on port 1, connecting would fail with `Disconnected`
+        match TlsTransport::connect("127.0.0.1", 1, None) {
+            Err(ErrorKind::InvalidConfig(_)) => {}
+            Err(e) => panic!("expected InvalidConfig, got {:?}", e),
+            Ok(_) => panic!("expected InvalidConfig"),
+        }
+    }
+
+    /// Serves one TLS connection on localhost, replying to the first message with `reply`.
+    fn tls_server(
+        reply: &'static str,
+    ) -> (u16, Arc<ClientConfig>, std::thread::JoinHandle<String>) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let cert_der = rustls::Certificate(cert.serialize_der().unwrap());
+        let key_der = rustls::PrivateKey(cert.serialize_private_key_der());
+
+        let mut server_config = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+        server_config
+            .set_single_cert(vec![cert_der.clone()], key_der)
+            .unwrap();
+        let mut client_config = ClientConfig::new();
+        client_config.root_store.add(&cert_der).unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let session = rustls::ServerSession::new(&Arc::new(server_config));
+            let mut stream = BufReader::new(StreamOwned::new(session, socket));
+            let mut request = String::new();
+            stream.read_line(&mut request).unwrap();
+            stream.get_mut().write_all(reply.as_bytes()).unwrap();
+            request
+        });
+        (port, Arc::new(client_config), server)
+    }
+
+    #[test]
+    fn sends_right_after_connecting_non_blocking() {
+        let (port, config, server) = tls_server("{\"status\":true}\n\n");
+        let mut transport =
+            TlsTransport::connect_with("localhost", port, Some(Duration::from_secs(5)), &config)
+                .unwrap();
+        // The streaming connection goes non-blocking before its first message
+        transport.set_nonblocking(true).unwrap();
+        transport
+            .send_message("{\"command\":\"ping\"}\n\n")
+            .unwrap();
+
+        let reply = loop {
+            match transport.recv_message() {
+                Err(ErrorKind::WouldBlock) => std::thread::sleep(Duration::from_millis(10)),
+                reply => break reply.unwrap(),
+            }
+        };
+        assert_eq!(reply, "{\"status\":true}\n");
+        assert_eq!(server.join().unwrap(), "{\"command\":\"ping\"}\n");
+    }
+}
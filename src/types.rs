@@ -1,9 +1,11 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
-#[derive(Serialize_repr, Deserialize_repr, Clone, Debug)]
+#[derive(Serialize_repr, Deserialize_repr, Clone, Copy, Debug, Default, PartialEq, Eq)]
 #[repr(u8)]
 pub enum OrderSide {
+    #[default]
     Buy,
     Sell,
     BuyLimit,
@@ -12,9 +14,33 @@ pub enum OrderSide {
     SellStop,
 }
 
-impl Default for OrderSide {
-    fn default() -> Self {
-        Self::Buy
+impl OrderSide {
+    /// Whether this side opens (or closes into) a long position.
+    pub fn is_buy(self) -> bool {
+        matches!(self, Self::Buy | Self::BuyLimit | Self::BuyStop)
+    }
+
+    /// Whether this side is only valid for pending (`OrderType::Pending`) orders.
+    pub fn is_pending(self) -> bool {
+        !matches!(self, Self::Buy | Self::Sell)
+    }
+
+    /// The limit order on the same side of the market.
+    pub fn limit(self) -> Self {
+        if self.is_buy() {
+            Self::BuyLimit
+        } else {
+            Self::SellLimit
+        }
+    }
+
+    /// The stop order on the same side of the market.
+    pub fn stop(self) -> Self {
+        if self.is_buy() {
+            Self::BuyStop
+        } else {
+            Self::SellStop
+        }
     }
 }
 
@@ -49,7 +75,7 @@ impl Into<usize> for OrderId {
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Default, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Default, Clone, Copy, Debug)]
 pub struct PositionId(pub usize);
 
 impl From<usize> for PositionId {
@@ -64,31 +90,30 @@ impl Into<usize> for PositionId {
     }
 }
 
-#[allow(dead_code)]
 #[derive(Deserialize, Default, Clone, Debug)]
 #[serde(default)]
 pub struct TradeRecord {
-    close_price: f64,
-    close_time: Option<u64>,
+    pub close_price: f64,
+    pub close_time: Option<u64>,
     #[serde(rename = "close_timeString")]
-    close_time_string: Option<String>,
-    closed: bool,
+    pub close_time_string: Option<String>,
+    pub closed: bool,
     #[serde(rename = "cmd")]
-    side: OrderSide,
-    comment: Option<String>,
-    commission: f64,
+    pub side: OrderSide,
+    pub comment: Option<String>,
+    pub commission: f64,
     #[serde(rename = "customComment")]
-    custom_comment: Option<String>,
-    digits: usize,
-    expiration: Option<u64>,
+    pub custom_comment: Option<String>,
+    pub digits: usize,
+    pub expiration: Option<u64>,
     #[serde(rename = "expirationString")]
-    expiration_string: Option<String>,
-    margin_rate: f64,
-    offset: u64,
-    open_price: f64,
-    open_time: Option<u64>,
+    pub expiration_string: Option<String>,
+    pub margin_rate: f64,
+    pub offset: u64,
+    pub open_price: f64,
+    pub open_time: Option<u64>,
     #[serde(rename = "open_timeString")]
-    open_time_string: Option<String>,
+    pub open_time_string: Option<String>,
     #[serde(rename = "order")]
     pub order: Option<OrderId>,
     #[serde(rename = "order2")]
@@ -105,10 +130,38 @@ pub struct TradeRecord {
     pub kind: OrderType,
 
     #[serde(rename = "nominalValue")]
-    nominal_value: Option<f64>,
-    spread: usize,
-    taxes: f64,
-    state: String,
+    pub nominal_value: Option<f64>,
+    pub spread: usize,
+    pub taxes: f64,
+    pub state: String,
+}
+
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RequestStatus {
+    Error = 0,
+    Pending = 1,
+    Accepted = 3,
+    Rejected = 4,
+}
+
+impl RequestStatus {
+    /// Whether the server is done processing the request.
+    pub fn is_final(self) -> bool {
+        self != Self::Pending
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TransactionStatus {
+    pub ask: f64,
+    pub bid: f64,
+    #[serde(rename = "customComment")]
+    pub custom_comment: Option<String>,
+    pub message: Option<String>,
+    pub order: OrderId,
+    #[serde(rename = "requestStatus")]
+    pub request_status: RequestStatus,
 }
 
 #[derive(Deserialize, Default, Clone, Debug)]
@@ -123,6 +176,234 @@ pub struct BalanceRecord {
     pub margin_level: f64,
 }
 
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MarginMode {
+    Forex = 101,
+    CfdLeveraged = 102,
+    Cfd = 103,
+}
+
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ProfitMode {
+    Forex = 5,
+    Cfd = 6,
+}
+
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum QuoteSource {
+    Fixed = 1,
+    Float = 2,
+    Depth = 3,
+    Cross = 4,
+}
+
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SwapType {
+    Points = 0,
+    Dollars = 1,
+    Interest = 2,
+    MarginCurrency = 3,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolRecord {
+    pub ask: f64,
+    pub bid: f64,
+    pub category_name: String,
+    pub contract_size: u64,
+    pub currency: String,
+    pub currency_pair: bool,
+    pub currency_profit: String,
+    pub description: String,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub expiration: Option<DateTime<Utc>>,
+    pub group_name: String,
+    pub high: f64,
+    pub initial_margin: i64,
+    pub instant_max_volume: u64,
+    pub leverage: f64,
+    pub long_only: bool,
+    pub lot_max: f64,
+    pub lot_min: f64,
+    pub lot_step: f64,
+    pub low: f64,
+    pub margin_hedged: i64,
+    pub margin_hedged_strong: bool,
+    pub margin_maintenance: Option<i64>,
+    pub margin_mode: MarginMode,
+    pub percentage: f64,
+    #[serde(default)]
+    pub pips_precision: u32,
+    /// Number of decimal digits of the price.
+    pub precision: u32,
+    pub profit_mode: ProfitMode,
+    #[serde(rename = "quoteId")]
+    pub quote_source: QuoteSource,
+    pub short_selling: bool,
+    pub spread_raw: f64,
+    pub spread_table: f64,
+    #[serde(with = "chrono::serde::ts_milliseconds_option")]
+    pub starting: Option<DateTime<Utc>>,
+    pub step_rule_id: i32,
+    pub stops_level: i32,
+    /// Day of the week (0 being Sunday) on which triple swap is charged.
+    #[serde(rename = "swap_rollover3days")]
+    pub swap_rollover_3_days: u8,
+    pub swap_enable: bool,
+    pub swap_long: f64,
+    pub swap_short: f64,
+    pub swap_type: SwapType,
+    pub symbol: String,
+    pub tick_size: f64,
+    pub tick_value: f64,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub time: DateTime<Utc>,
+    pub trailing_enabled: bool,
+    #[serde(rename = "type")]
+    pub kind: i32,
+}
+
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Period {
+    M1 = 1,
+    M5 = 5,
+    M15 = 15,
+    M30 = 30,
+    H1 = 60,
+    H4 = 240,
+    D1 = 1440,
+    W1 = 10080,
+    MN1 = 43200,
+}
+
+impl Period {
+    pub fn minutes(self) -> u32 {
+        self as u32
+    }
+}
+
+/// A candle as sent by the chart commands, with prices relative to `open`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct RateInfoRecord {
+    pub close: f64,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub ctm: DateTime<Utc>,
+    pub high: f64,
+    pub low: f64,
+    pub open: f64,
+    pub vol: f64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ChartResponse {
+    pub digits: i32,
+    #[serde(rename = "rateInfos")]
+    pub rate_infos: Vec<RateInfoRecord>,
+}
+
+impl ChartResponse {
+    /// Converts the scaled, open-relative prices into absolute ones.
+    pub fn candles(&self) -> Vec<Candle> {
+        let scale = 10f64.powi(-self.digits);
+        self.rate_infos
+            .iter()
+            .map(|rate| {
+                let open = rate.open * scale;
+                Candle {
+                    ctm: rate.ctm,
+                    open,
+                    high: open + rate.high * scale,
+                    low: open + rate.low * scale,
+                    close: open + rate.close * scale,
+                    vol: rate.vol,
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Candle {
+    pub ctm: DateTime<Utc>,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub vol: f64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CommissionRecord {
+    /// Commission in the account currency.
+    pub commission: f64,
+    /// Exchange rate between the account currency and the symbol's currency.
+    #[serde(rename = "rateOfExchange")]
+    pub rate_of_exchange: Option<f64>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MarginTradeRecord {
+    /// Required margin in the account currency.
+    pub margin: f64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MarginLevelRecord {
+    pub balance: f64,
+    pub credit: f64,
+    pub currency: String,
+    pub equity: f64,
+    pub margin: f64,
+    pub margin_free: f64,
+    pub margin_level: f64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserDataRecord {
+    pub company_unit: i32,
+    pub currency: String,
+    pub group: String,
+    pub ib_account: bool,
+    pub leverage: i32,
+    pub leverage_multiplier: f64,
+    pub spread_type: Option<String>,
+    pub trailing_stop: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct NewsRecord {
+    pub body: String,
+    /// Only sent in response to `getNews`, not on the news stream.
+    pub bodylen: Option<usize>,
+    pub key: String,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub time: DateTime<Utc>,
+    pub title: String,
+}
+
+/// Introducing broker history entry. The server may leave any of the fields out.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IbRecord {
+    pub close_price: Option<f64>,
+    pub login: Option<String>,
+    pub nominal: Option<f64>,
+    pub open_price: Option<f64>,
+    pub side: Option<OrderSide>,
+    pub surname: Option<String>,
+    pub symbol: Option<String>,
+    #[serde(default, with = "chrono::serde::ts_milliseconds_option")]
+    pub timestamp: Option<DateTime<Utc>>,
+    pub volume: Option<f64>,
+}
+
 pub struct Symbol(pub String);
 
 impl Symbol {
@@ -142,3 +423,36 @@ impl From<String> for Symbol {
         Self(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_candles_from_the_xapi_sample() {
+        let response: ChartResponse = serde_json::from_str(
+            r#"{
+                "digits": 4,
+                "rateInfos": [{
+                    "close": 1.0,
+                    "ctm": 1389362640000,
+                    "ctmString": "Jan 10, 2014 3:04:00 PM",
+                    "high": 6.0,
+                    "low": 0.0,
+                    "open": 41848.0,
+                    "vol": 0.0
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let candles = response.candles();
+        assert_eq!(candles.len(), 1);
+        let candle = &candles[0];
+        assert_eq!(candle.ctm.timestamp_millis(), 1389362640000);
+        assert!((candle.open - 4.1848).abs() < 1e-9);
+        assert!((candle.close - 4.1849).abs() < 1e-9);
+        assert!((candle.high - 4.1854).abs() < 1e-9);
+        assert!((candle.low - 4.1848).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,156 @@
+use rustls::{ClientSession, StreamOwned};
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::time::Duration;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::{Message, WebSocket};
+
+use crate::transport::{
+    connect_tcp, dns_name, read_error, shutdown_tcp, tls_stream, Transport, RUSTLS_CLIENT_CONFIG,
+};
+use crate::ErrorKind;
+use crate::Result;
+
+enum WsStream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientSession, TcpStream>>),
+}
+
+impl WsStream {
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            WsStream::Plain(socket) => socket,
+            WsStream::Tls(stream) => &stream.sock,
+        }
+    }
+}
+
+impl Read for WsStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            WsStream::Plain(socket) => socket.read(buf),
+            WsStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for WsStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            WsStream::Plain(socket) => socket.write(buf),
+            WsStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            WsStream::Plain(socket) => socket.flush(),
+            WsStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+fn ws_error(e: tungstenite::Error, nonblocking: bool) -> ErrorKind {
+    match e {
+        tungstenite::Error::Io(e) => read_error(e, nonblocking),
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            ErrorKind::Disconnected(std::io::ErrorKind::NotConnected.into())
+        }
+        e => ErrorKind::Disconnected(std::io::Error::other(e)),
+    }
+}
+
+/// The same JSON messages as `TlsTransport`, one per WebSocket text frame.
+pub struct WebSocketTransport {
+    socket: WebSocket<WsStream>,
+    nonblocking: bool,
+}
+
+impl WebSocketTransport {
+    /// Connects to a `ws://` or `wss://` URL.
+    pub fn connect(url: &str, timeout: Option<Duration>) -> Result<Self> {
+        let request = url
+            .into_client_request()
+            .map_err(|e| ErrorKind::InvalidConfig(e.to_string()))?;
+        let uri = request.uri();
+        let host = uri
+            .host()
+            .ok_or_else(|| ErrorKind::InvalidConfig(format!("no host in {}", url)))?
+            .to_owned();
+        let secure = uri.scheme_str() == Some("wss");
+        let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+
+        let dns_name = if secure { Some(dns_name(&host)?) } else { None };
+
+        let socket = connect_tcp(&host, port, timeout)?;
+        let stream = match dns_name {
+            Some(dns_name) => WsStream::Tls(Box::new(tls_stream(
+                &RUSTLS_CLIENT_CONFIG,
+                dns_name,
+                socket,
+            )?)),
+            None => WsStream::Plain(socket),
+        };
+
+        let (socket, _response) = tungstenite::client(request, stream)
+            .map_err(|e| ErrorKind::Disconnected(std::io::Error::other(e.to_string())))?;
+        Ok(Self {
+            socket,
+            nonblocking: false,
+        })
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn send_message(&mut self, message: &str) -> Result<()> {
+        match self.socket.send(Message::Text(message.to_owned())) {
+            Ok(()) => Ok(()),
+            // The frame is queued and goes out with the next read or write
+            Err(tungstenite::Error::Io(e))
+                if self.nonblocking && e.kind() == std::io::ErrorKind::WouldBlock =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(ws_error(e, self.nonblocking)),
+        }
+    }
+
+    fn recv_message(&mut self) -> Result<String> {
+        loop {
+            match self.socket.read() {
+                Ok(Message::Text(text)) => return Ok(text),
+                Ok(Message::Binary(data)) => {
+                    return String::from_utf8(data).map_err(|_| ErrorKind::InvalidResponse)
+                }
+                // Pings are answered by tungstenite itself
+                Ok(_) => continue,
+                Err(e) => return Err(ws_error(e, self.nonblocking)),
+            }
+        }
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        self.socket
+            .get_ref()
+            .tcp()
+            .set_nonblocking(nonblocking)
+            .map_err(ErrorKind::Disconnected)?;
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.socket
+            .get_ref()
+            .tcp()
+            .set_read_timeout(timeout)
+            .map_err(ErrorKind::Disconnected)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        // Best effort, the server may already be gone
+        let _ = self.socket.close(None);
+        let _ = self.socket.flush();
+        shutdown_tcp(self.socket.get_ref().tcp())
+    }
+}
@@ -0,0 +1,161 @@
+//! A stand-in for the xAPI servers, speaking JSON over WebSocket.
+
+use std::net::TcpListener;
+use std::thread::JoinHandle;
+
+use serde_json::{json, Value};
+use tungstenite::Message;
+
+/// Accepts a single WebSocket client and answers every command it sends with `reply`.
+pub fn stand_in_server<F>(mut reply: F) -> (u16, JoinHandle<Vec<String>>)
+where
+    F: FnMut(&Value) -> Vec<Value> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut socket = tungstenite::accept(stream).unwrap();
+        let mut commands = Vec::new();
+        while let Ok(Message::Text(text)) = socket.read() {
+            let command: Value = serde_json::from_str(&text).unwrap();
+            commands.push(command["command"].as_str().unwrap().to_owned());
+            for response in reply(&command) {
+                // The client may have hung up already
+                if socket.send(Message::Text(response.to_string())).is_err() {
+                    return commands;
+                }
+            }
+            if command["command"] == "logout" {
+                break;
+            }
+        }
+        commands
+    });
+    (port, handle)
+}
+
+/// Too big for `json!`.
+fn eurusd() -> Value {
+    serde_json::from_str(
+        r#"{
+        "ask": 1.1002,
+        "bid": 1.1,
+        "categoryName": "FX",
+        "contractSize": 100000,
+        "currency": "EUR",
+        "currencyPair": true,
+        "currencyProfit": "USD",
+        "description": "Euro to American Dollar",
+        "expiration": null,
+        "groupName": "Major",
+        "high": 1.11,
+        "initialMargin": 0,
+        "instantMaxVolume": 0,
+        "leverage": 3.33,
+        "longOnly": false,
+        "lotMax": 100.0,
+        "lotMin": 0.01,
+        "lotStep": 0.01,
+        "low": 1.09,
+        "marginHedged": 0,
+        "marginHedgedStrong": false,
+        "marginMaintenance": null,
+        "marginMode": 101,
+        "percentage": 100.0,
+        "precision": 5,
+        "profitMode": 5,
+        "quoteId": 1,
+        "shortSelling": true,
+        "spreadRaw": 0.0002,
+        "spreadTable": 0.0002,
+        "starting": null,
+        "stepRuleId": 1,
+        "stopsLevel": 0,
+        "swap_rollover3days": 3,
+        "swapEnable": true,
+        "swapLong": -2.5,
+        "swapShort": 0.1,
+        "swapType": 0,
+        "symbol": "EURUSD",
+        "tickSize": 0.00001,
+        "tickValue": 1.0,
+        "time": 1392211379731,
+        "timeString": "Feb 12, 2014 2:22:59 PM",
+        "trailingEnabled": true,
+        "type": 21
+    }"#,
+    )
+    .unwrap()
+}
+
+pub fn request_reply(command: &Value) -> Vec<Value> {
+    let response = match command["command"].as_str().unwrap() {
+        "login" => json!({ "status": true, "streamSessionId": "session" }),
+        "getCurrentUserData" => json!({
+            "status": true,
+            "returnData": {
+                "companyUnit": 8,
+                "currency": "EUR",
+                "group": "demoEUR",
+                "ibAccount": false,
+                "leverage": 1,
+                "leverageMultiplier": 0.25,
+                "spreadType": "FLOAT",
+                "trailingStop": false
+            }
+        }),
+        "getVersion" => json!({ "status": true, "returnData": { "version": "2.5.0" } }),
+        "getServerTime" => json!({
+            "status": true,
+            "returnData": { "time": 1392211379731u64, "timeString": "Feb 12, 2014 2:22:59 PM" }
+        }),
+        "getSymbol" => json!({ "status": true, "returnData": eurusd() }),
+        "getStepRules" => json!({
+            "status": true,
+            "returnData": [{
+                "id": 1,
+                "name": "Forex",
+                "steps": [{ "fromValue": 0.1, "step": 0.00001 }]
+            }]
+        }),
+        "tradeTransaction" => json!({ "status": true, "returnData": { "order": 43 } }),
+        "tradeTransactionStatus" => json!({
+            "status": true,
+            "returnData": {
+                "ask": 1.1,
+                "bid": 1.0,
+                "customComment": null,
+                "message": null,
+                "order": 43,
+                "requestStatus": 1
+            }
+        }),
+        "ping" | "logout" => json!({ "status": true }),
+        _ => json!({ "status": false, "errorCode": "EX000", "errorDescr": "unknown command" }),
+    };
+    vec![response]
+}
+
+pub fn streaming_reply(command: &Value) -> Vec<Value> {
+    assert_eq!(command["streamSessionId"], "session");
+    match command["command"].as_str().unwrap() {
+        "getKeepAlive" => vec![json!({ "command": "keepAlive", "data": { "timestamp": 1 } })],
+        // Not a streaming message, the client has to skip it
+        "getBalance" => vec![
+            json!({ "status": true }),
+            json!({
+                "command": "balance",
+                "data": {
+                    "balance": 995800269.43,
+                    "credit": 1000.00,
+                    "equity": 995985397.56,
+                    "margin": 572634.43,
+                    "marginFree": 995227635.00,
+                    "marginLevel": 173930.41
+                }
+            }),
+        ],
+        _ => vec![],
+    }
+}
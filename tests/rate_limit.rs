@@ -0,0 +1,63 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::*;
+use xtb_rs::*;
+
+#[test]
+fn rate_limits_orders() {
+    let (request_port, request_server) = stand_in_server(request_reply);
+    let (streaming_port, streaming_server) = stand_in_server(streaming_reply);
+
+    let spacing = Duration::from_millis(200);
+    let mut xtb = XTBBuilder::default()
+        .transport(TransportKind::WebSocket)
+        .request_url(format!("ws://127.0.0.1:{}/demo", request_port))
+        .streaming_url(format!("ws://127.0.0.1:{}/demoStream", streaming_port))
+        .rate_limit(RateLimit::new(spacing, 1))
+        .queue_orders(false)
+        .connect(1000, "password")
+        .unwrap();
+
+    // The symbol and step rules fetched right before the order don't count against it
+    let order = xtb.place_limit(
+        "EURUSD",
+        OrderSide::Buy,
+        0.1,
+        1.05,
+        PendingOptions::default(),
+    );
+    assert_eq!(order.unwrap(), OrderId(43));
+
+    // Orders sent back to back are rejected
+    std::thread::sleep(spacing);
+    assert_eq!(xtb.buy("EURUSD", 0.1).unwrap(), OrderId(43));
+    match xtb.buy("EURUSD", 0.1) {
+        Err(ErrorKind::RateLimited(wait)) => assert!(wait <= spacing),
+        other => panic!("expected a rate limit error, got {:?}", other),
+    }
+
+    // Other requests wait for their turn instead
+    let start = Instant::now();
+    xtb.ping().unwrap();
+    assert!(start.elapsed() >= spacing - Duration::from_millis(50));
+
+    xtb.shutdown().unwrap();
+    let requests = request_server.join().unwrap();
+    assert_eq!(
+        requests,
+        [
+            "login",
+            "getCurrentUserData",
+            "getVersion",
+            "getSymbol",
+            "getStepRules",
+            "tradeTransaction",
+            "tradeTransaction",
+            "ping",
+            "logout"
+        ]
+    );
+    streaming_server.join().unwrap();
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::*;
+use xtb_rs::*;
+
+#[test]
+fn connects_over_websocket() {
+    let (request_port, request_server) = stand_in_server(request_reply);
+    let (streaming_port, streaming_server) = stand_in_server(streaming_reply);
+
+    let mut xtb = XTBBuilder::default()
+        .transport(TransportKind::WebSocket)
+        .request_url(format!("ws://127.0.0.1:{}/demo", request_port))
+        .streaming_url(format!("ws://127.0.0.1:{}/demoStream", streaming_port))
+        .connect_timeout(Duration::from_secs(5))
+        .request_timeout(Duration::from_secs(5))
+        .connect(1000, "password")
+        .unwrap();
+
+    assert_eq!(xtb.api_version(), "2.5.0");
+    assert_eq!(xtb.account_info().currency, "EUR");
+    assert_eq!(xtb.server_time().unwrap().timestamp_millis(), 1392211379731);
+
+    let balance = xtb.start_balance().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut received = None;
+    while received.is_none() && Instant::now() < deadline {
+        received = xtb.messages().find_map(|msg| match msg {
+            StreamingMessage::Balance { data } => Some(data),
+            _ => None,
+        });
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(received.unwrap().credit, 1000.0);
+
+    balance.unsubscribe().unwrap();
+    xtb.shutdown().unwrap();
+
+    let requests = request_server.join().unwrap();
+    assert_eq!(
+        requests,
+        [
+            "login",
+            "getCurrentUserData",
+            "getVersion",
+            "getServerTime",
+            "logout"
+        ]
+    );
+    let streams = streaming_server.join().unwrap();
+    assert_eq!(
+        streams,
+        ["getKeepAlive", "getBalance", "stopBalance", "stopKeepAlive"]
+    );
+}
+
+#[test]
+fn timed_out_connection_is_not_reused() {
+    let (request_port, request_server) = stand_in_server(|command| {
+        if command["command"] == "getServerTime" {
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        request_reply(command)
+    });
+    let (streaming_port, streaming_server) = stand_in_server(streaming_reply);
+
+    let mut xtb = XTBBuilder::default()
+        .transport(TransportKind::WebSocket)
+        .request_url(format!("ws://127.0.0.1:{}/demo", request_port))
+        .streaming_url(format!("ws://127.0.0.1:{}/demoStream", streaming_port))
+        .request_timeout(Duration::from_millis(200))
+        .rate_limit(RateLimit::new(Duration::ZERO, 1))
+        .connect(1000, "password")
+        .unwrap();
+
+    assert!(matches!(xtb.server_time(), Err(ErrorKind::Timeout)));
+    // The late reply must not be taken for the answer to these
+    assert!(matches!(xtb.server_time(), Err(ErrorKind::Disconnected(_))));
+    assert!(matches!(xtb.ping(), Err(ErrorKind::Disconnected(_))));
+
+    drop(xtb);
+    request_server.join().unwrap();
+    streaming_server.join().unwrap();
+}
+
+#[test]
+fn pending_timeout_keeps_the_order() {
+    let (request_port, request_server) = stand_in_server(request_reply);
+    let (streaming_port, streaming_server) = stand_in_server(streaming_reply);
+
+    let mut xtb = XTBBuilder::default()
+        .transport(TransportKind::WebSocket)
+        .request_url(format!("ws://127.0.0.1:{}/demo", request_port))
+        .streaming_url(format!("ws://127.0.0.1:{}/demoStream", streaming_port))
+        .rate_limit(RateLimit::new(Duration::ZERO, 1))
+        .connect(1000, "password")
+        .unwrap();
+
+    let timeout = Duration::from_millis(300);
+    let start = Instant::now();
+    let symbol = Symbol("EURUSD".to_owned());
+    match xtb.send_transaction_and_wait(symbol.buy(0.1), timeout) {
+        Err(ErrorKind::PendingTimeout(status)) => {
+            assert_eq!(status.order, OrderId(43));
+            assert_eq!(status.request_status, RequestStatus::Pending);
+        }
+        other => panic!("expected PendingTimeout, got {:?}", other),
+    }
+    // The last poll waits only for what's left of the timeout
+    assert!(start.elapsed() < timeout + Duration::from_millis(150));
+
+    xtb.shutdown().unwrap();
+    request_server.join().unwrap();
+    streaming_server.join().unwrap();
+}